@@ -0,0 +1,58 @@
+//! Compares decoding every fetched word against reading from the decoded instruction cache.
+//!
+//! Run with `cargo bench --bench decode`.
+
+use std::{hint::black_box, time::Instant};
+
+use chirp_8::{instruction::Instruction, memory::Memory};
+
+const ITERATIONS: usize = 20_000;
+
+/// A loop body touching most of the instruction set, roughly what a game's main loop looks like
+const PROGRAM: &[u8] = &[
+    0x60, 0x05, 0x61, 0x0A, 0x70, 0x01, 0x81, 0x04, 0x81, 0x15, 0x81, 0x26, 0x30, 0x10, 0x40, 0x20,
+    0x51, 0x00, 0x91, 0x00, 0xA3, 0x00, 0xF1, 0x1E, 0xC2, 0x0F, 0x82, 0x12, 0x82, 0x23, 0x82, 0x33,
+    0x8E, 0x1E, 0xF2, 0x07, 0xF2, 0x15, 0xF2, 0x18, 0xF0, 0x29, 0xE1, 0x9E, 0xE1, 0xA1, 0xD0, 0x15,
+    0x00, 0xE0, 0x12, 0x00,
+];
+
+fn main() {
+    let mut memory = Memory::new(PROGRAM);
+    let end = Memory::ROM_OFFSET + PROGRAM.len() as u16;
+
+    let uncached = measure(|| {
+        for pc in (Memory::ROM_OFFSET..end).step_by(2) {
+            black_box(Instruction::decode(memory.read_u16(black_box(pc)).unwrap()));
+        }
+    });
+
+    let cached = measure(|| {
+        for pc in (Memory::ROM_OFFSET..end).step_by(2) {
+            black_box(memory.read_instruction(black_box(pc)).unwrap());
+        }
+    });
+
+    let per_inst = |nanos: f64| nanos / (ITERATIONS * PROGRAM.len() / 2) as f64;
+
+    println!(
+        "read_u16 + decode: {:>6.2} ns/instruction",
+        per_inst(uncached)
+    );
+    println!(
+        "read_instruction:  {:>6.2} ns/instruction",
+        per_inst(cached)
+    );
+    println!("speedup:           {:>6.2}x", uncached / cached);
+}
+
+fn measure(mut f: impl FnMut()) -> f64 {
+    // warm up, this also fills the cache
+    f();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+
+    start.elapsed().as_nanos() as f64
+}
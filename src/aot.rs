@@ -0,0 +1,336 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+};
+
+use crate::{
+    instruction::{Instruction, MAX_BLOCK_LEN},
+    memory::Memory,
+};
+
+/// A straight-line run of instructions discovered in the ROM
+struct Block {
+    /// Instructions with their addresses
+    insts: Vec<(u16, Instruction)>,
+    /// Address right after the last instruction
+    end: u16,
+}
+
+/// Translates a ROM into a Rust module that runs it against [`crate::machine::Machine`].
+///
+/// Code reachable from the entry point is split into basic blocks, each becoming a function.
+/// The interpreter takes over for anything that wasn't discovered statically (targets of
+/// `BNNN`), for blocks whose memory was modified at runtime and for undecodable words.
+///
+/// The translation is partial: machine calls, clearing the screen, returns, `BNNN`, random
+/// numbers and waiting for a key still go through [`crate::machine::Machine::execute`],
+/// everything else becomes inline Rust.
+pub fn translate(rom: &[u8], name: &str) -> String {
+    let blocks = discover(rom);
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        "//! Generated by `chirp-8 aot` from {name}, do not edit.\n\
+         //!\n\
+         //! Create a `Machine` from [`ROM`] and call [`run_block`] in place of `Machine::step`.\n"
+    )
+    .unwrap();
+    writeln!(out, "use chirp_8::{{").unwrap();
+    writeln!(out, "    machine::Machine,").unwrap();
+    writeln!(out, "    memory::{{Memory, RegIdx}},").unwrap();
+    writeln!(out, "}};").unwrap();
+    writeln!(out, "use color_eyre::Result;\n").unwrap();
+
+    write!(out, "const V: [RegIdx; 16] = [").unwrap();
+    for idx in 0..16 {
+        write!(out, "\n    RegIdx::new({idx:#x}),").unwrap();
+    }
+    writeln!(out, "\n];\n").unwrap();
+
+    write!(out, "pub const ROM: &[u8] = &[").unwrap();
+    for (i, byte) in rom.iter().enumerate() {
+        if i % 16 == 0 {
+            write!(out, "\n   ").unwrap();
+        }
+        write!(out, " {byte:#04x},").unwrap();
+    }
+    writeln!(out, "\n];\n").unwrap();
+
+    writeln!(
+        out,
+        "/// Runs the block starting at `pc`, returns how many instructions were executed.\n\
+         pub fn run_block(m: &mut Machine) -> Result<usize> {{\n    \
+             match m.pc {{"
+    )
+    .unwrap();
+    for (start, block) in &blocks {
+        writeln!(
+            out,
+            "        {start:#06x} if unmodified(m, {start:#06x}, {:#06x}) => block_{start:04x}(m),",
+            block.end
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "        _ => m.step().map(|()| 1),\n    \
+             }}\n\
+         }}\n"
+    )
+    .unwrap();
+
+    writeln!(
+        out,
+        "/// Whether memory in `start..end` still holds the code that was translated.\n\
+         fn unmodified(m: &Machine, start: usize, end: usize) -> bool {{\n    \
+             let rom = &ROM[start - Memory::ROM_OFFSET as usize..end - Memory::ROM_OFFSET as usize];\n\n    \
+             m.memory.as_bytes()[start..end] == *rom\n\
+         }}"
+    )
+    .unwrap();
+
+    for (start, block) in &blocks {
+        writeln!(
+            out,
+            "\nfn block_{start:04x}(m: &mut Machine) -> Result<usize> {{"
+        )
+        .unwrap();
+
+        for &(addr, inst) in &block.insts {
+            let word = u16::from_be_bytes([
+                rom[(addr - Memory::ROM_OFFSET) as usize],
+                rom[(addr - Memory::ROM_OFFSET + 1) as usize],
+            ]);
+
+            writeln!(out, "    // {addr:#06x}: {word:04x}\n    m.cycles += 1;").unwrap();
+            emit(&mut out, addr + 2, inst);
+        }
+
+        if !block
+            .insts
+            .last()
+            .is_some_and(|&(_, inst)| inst.ends_block())
+        {
+            writeln!(out, "    m.pc = {:#06x};", block.end).unwrap();
+        }
+        writeln!(out, "\n    Ok({})\n}}", block.insts.len()).unwrap();
+    }
+
+    out
+}
+
+/// Finds every block reachable from the entry point through static control flow.
+fn discover(rom: &[u8]) -> BTreeMap<u16, Block> {
+    let mut blocks = BTreeMap::new();
+    let mut queue = BTreeSet::from([Memory::ROM_OFFSET]);
+
+    while let Some(start) = queue.pop_first() {
+        if blocks.contains_key(&start) {
+            continue;
+        }
+
+        let mut insts = Vec::new();
+        let mut pc = start;
+
+        while let Some(inst) = fetch(rom, pc) {
+            insts.push((pc, inst));
+            pc += 2;
+
+            if inst.ends_block() {
+                queue.extend(successors(inst, pc));
+                break;
+            }
+
+            if insts.len() == MAX_BLOCK_LEN {
+                queue.insert(pc);
+                break;
+            }
+        }
+
+        // undecodable or outside of the ROM, the interpreter will deal with it
+        if !insts.is_empty() {
+            blocks.insert(start, Block { insts, end: pc });
+        }
+    }
+
+    blocks
+}
+
+fn fetch(rom: &[u8], pc: u16) -> Option<Instruction> {
+    let offset = pc.checked_sub(Memory::ROM_OFFSET)? as usize;
+    let word = rom.get(offset..offset + 2)?;
+
+    Instruction::decode(u16::from_be_bytes([word[0], word[1]]))
+}
+
+/// Addresses execution may continue at, `next` is the address following the instruction
+fn successors(inst: Instruction, next: u16) -> Vec<u16> {
+    use Instruction::*;
+
+    match inst {
+        Jump(nnn) => vec![nnn],
+        Call(nnn) => vec![nnn, next],
+        // return addresses are covered by calls, BNNN targets are unknown
+        Return | JumpV0(_) => vec![],
+        SkipEqIm(..) | SkipNeIm(..) | SkipEq(..) | SkipNe(..) | SkipKeyEq(_) | SkipKeyNe(_) => {
+            vec![next, next + 2]
+        }
+        GetKey(_) => vec![next - 2, next],
+        _ => vec![next],
+    }
+}
+
+/// Emits Rust code executing `inst`, `next` is the address following it.
+fn emit(out: &mut String, next: u16, inst: Instruction) {
+    use Instruction::*;
+
+    let v = |reg: crate::memory::RegIdx| format!("V[{:#x}]", reg.as_u8());
+
+    let code = match inst {
+        SetIm(vx, nn) => format!("m.regs[{}] = {nn:#04x};", v(vx)),
+        AddIm(vx, nn) => format!(
+            "m.regs[{x}] = m.regs[{x}].wrapping_add({nn:#04x});",
+            x = v(vx)
+        ),
+        SetIndex(nnn) => format!("m.index = {nnn:#05x};"),
+        Set(vx, vy) => format!("m.regs[{}] = m.regs[{}];", v(vx), v(vy)),
+        Or(vx, vy) => format!("m.regs[{}] |= m.regs[{}];", v(vx), v(vy)),
+        And(vx, vy) => format!("m.regs[{}] &= m.regs[{}];", v(vx), v(vy)),
+        Xor(vx, vy) => format!("m.regs[{}] ^= m.regs[{}];", v(vx), v(vy)),
+        Add(vx, vy) => format!(
+            "let (result, overflow) = m.regs[{x}].overflowing_add(m.regs[{y}]);\n    \
+             (m.regs[{x}], m.regs[V[0xf]]) = (result, overflow as u8);",
+            x = v(vx),
+            y = v(vy)
+        ),
+        Sub(vx, vy) => format!(
+            "let (x, y) = (m.regs[{x}], m.regs[{y}]);\n    \
+             m.regs[{x}] = x.wrapping_sub(y);\n    \
+             m.regs[V[0xf]] = (x >= y) as u8;",
+            x = v(vx),
+            y = v(vy)
+        ),
+        SubOpp(vx, vy) => format!(
+            "let (x, y) = (m.regs[{x}], m.regs[{y}]);\n    \
+             m.regs[{x}] = y.wrapping_sub(x);\n    \
+             m.regs[V[0xf]] = (y >= x) as u8;",
+            x = v(vx),
+            y = v(vy)
+        ),
+        Jump(nnn) => format!("m.pc = {nnn:#05x};"),
+        Call(nnn) => format!("m.stack.push({next:#06x});\n    m.pc = {nnn:#05x};"),
+        SkipEqIm(vx, nn) => skip(next, &format!("m.regs[{}] == {nn:#04x}", v(vx))),
+        SkipNeIm(vx, nn) => skip(next, &format!("m.regs[{}] != {nn:#04x}", v(vx))),
+        SkipEq(vx, vy) => skip(next, &format!("m.regs[{}] == m.regs[{}]", v(vx), v(vy))),
+        SkipNe(vx, vy) => skip(next, &format!("m.regs[{}] != m.regs[{}]", v(vx), v(vy))),
+        SkipKeyEq(vx) => skip(next, &format!("m.keys()[m.regs[{}] as usize]", v(vx))),
+        SkipKeyNe(vx) => skip(next, &format!("!m.keys()[m.regs[{}] as usize]", v(vx))),
+        Shr(vx, vy) => format!(
+            "if m.quirks.old_shift {{\n        m.regs[{x}] = m.regs[{y}];\n    }}\n    \
+             m.regs[V[0xf]] = m.regs[{x}] & 1;\n    \
+             m.regs[{x}] >>= 1;",
+            x = v(vx),
+            y = v(vy)
+        ),
+        Shl(vx, vy) => format!(
+            "if m.quirks.old_shift {{\n        m.regs[{x}] = m.regs[{y}];\n    }}\n    \
+             m.regs[V[0xf]] = m.regs[{x}] >> 7;\n    \
+             m.regs[{x}] <<= 1;",
+            x = v(vx),
+            y = v(vy)
+        ),
+        GetDelay(vx) => format!("m.regs[{}] = m.delay_timer;", v(vx)),
+        SetDelay(vx) => format!("m.delay_timer = m.regs[{}];", v(vx)),
+        SetSound(vx) => format!("m.set_sound_timer(m.regs[{}]);", v(vx)),
+        AddIndex(vx) => format!(
+            "m.index += m.regs[{}] as u16;\n    \
+             if m.quirks.index_overflow && m.index >= 4096 {{\n        \
+                 m.regs[V[0xf]] = 1;\n    \
+             }}",
+            v(vx)
+        ),
+        IndexCharacter(vx) => format!(
+            "m.index = Memory::FONT_OFFSET + 5 * (m.regs[{}] as u16 & 0x0f);",
+            v(vx)
+        ),
+        // these can fail, the error leaves `pc` past the instruction like in the interpreter
+        Draw(vx, vy, n) => format!(
+            "m.pc = {next:#06x};\n    m.draw({}, {}, {n})?;",
+            v(vx),
+            v(vy)
+        ),
+        SetBcd(vx) => format!(
+            "m.pc = {next:#06x};\n    \
+             let x = m.regs[{}];\n    \
+             m.memory.write_u8(m.index, x / 100)?;\n    \
+             m.memory.write_u8(m.index + 1, x / 10 % 10)?;\n    \
+             m.memory.write_u8(m.index + 2, x % 10)?;",
+            v(vx)
+        ),
+        RegStore(vx) => format!(
+            "m.pc = {next:#06x};\n    \
+             for (addr, reg) in (m.index..).zip(&V[..={x:#x}]) {{\n        \
+                 m.memory.write_u8(addr, m.regs[*reg])?;\n    \
+             }}\n    \
+             if m.quirks.old_store_load {{\n        \
+                 m.index += {len:#x};\n    \
+             }}",
+            x = vx.as_u8(),
+            len = vx.as_u8() + 1
+        ),
+        RegLoad(vx) => format!(
+            "m.pc = {next:#06x};\n    \
+             for (addr, reg) in (m.index..).zip(&V[..={x:#x}]) {{\n        \
+                 m.regs[*reg] = m.memory.read_u8(addr)?;\n    \
+             }}\n    \
+             if m.quirks.old_store_load {{\n        \
+                 m.index += {len:#x};\n    \
+             }}",
+            x = vx.as_u8(),
+            len = vx.as_u8() + 1
+        ),
+        // these need machine state only the interpreter has, or can't be resolved statically
+        Return => execute(next, "Return"),
+        MachineCall(nnn) => execute(next, &format!("MachineCall({nnn:#05x})")),
+        ClearScreen => execute(next, "ClearScreen"),
+        JumpV0(nnn) => execute(next, &format!("JumpV0({nnn:#05x})")),
+        RandAnd(vx, nn) => execute(next, &format!("RandAnd({}, {nn:#04x})", v(vx))),
+        GetKey(vx) => execute(next, &format!("GetKey({})", v(vx))),
+    };
+
+    writeln!(out, "    {code}").unwrap();
+}
+
+fn skip(next: u16, cond: &str) -> String {
+    format!("m.pc = {next:#06x};\n    if {cond} {{\n        m.pc += 2;\n    }}")
+}
+
+fn execute(next: u16, inst: &str) -> String {
+    format!("m.pc = {next:#06x};\n    m.execute(chirp_8::instruction::Instruction::{inst})?;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discover_calls_and_skips() {
+        // 0x200: call 0x208, 0x202: skip if V0 == 0, 0x204: jump 0x200, 0x206: jump 0x206
+        // 0x208: return
+        let blocks = discover(&[0x22, 0x08, 0x30, 0x00, 0x12, 0x00, 0x12, 0x06, 0x00, 0xEE]);
+
+        assert_eq!(
+            blocks.keys().copied().collect::<Vec<_>>(),
+            [0x200, 0x202, 0x204, 0x206, 0x208]
+        );
+    }
+
+    #[test]
+    fn stop_at_undecodable() {
+        let blocks = discover(&[0x60, 0x01, 0xFF, 0xFF]);
+
+        assert_eq!(blocks[&0x200].insts.len(), 1);
+        assert_eq!(blocks[&0x200].end, 0x202);
+    }
+}
@@ -0,0 +1,425 @@
+use color_eyre::{eyre::bail, Result};
+
+use crate::memory::Memory;
+
+/// A minimal RCA CDP1802 core, used to run machine code subroutines called with `0NNN`.
+///
+/// Subroutines are entered the same way the COSMAC VIP interpreter enters them: R3 becomes the
+/// program counter and the routine hands control back to CHIP-8 by executing `D4` (`SEP R4`).
+/// I/O instructions are accepted, but there are no devices attached to the bus.
+#[derive(Debug, Clone)]
+pub struct Cdp1802 {
+    /// General purpose 16-bit registers R0 - RF
+    pub r: [u16; 16],
+    /// Accumulator
+    pub d: u8,
+    /// Carry/borrow flag
+    pub df: bool,
+    /// Index of the program counter register
+    pub p: u8,
+    /// Index of the data pointer register
+    pub x: u8,
+    /// Holds X and P after an interrupt or `MARK`
+    pub t: u8,
+    /// Interrupt enable
+    pub ie: bool,
+    /// Q output flip-flop
+    pub q: bool,
+}
+
+impl Cdp1802 {
+    /// Where the VIP interpreter keeps V0 - VF, routines read and write them there
+    pub const VARIABLES: u16 = 0x0EF0;
+    /// Initial value of R2, the stack pointer used by the VIP interpreter
+    pub const STACK: u16 = 0x0ECF;
+    /// Register holding the CHIP-8 index register during a call
+    pub const INDEX_REG: usize = 0xA;
+    /// Register holding the CHIP-8 program counter during a call
+    pub const PC_REG: usize = 5;
+    /// Upper bound on instructions executed by a single subroutine before giving up
+    pub const MAX_STEPS: usize = 1_000_000;
+
+    /// Register used as program counter while a subroutine runs
+    const CALL_REG: u8 = 3;
+    /// Register the interpreter runs from, `SEP R4` returns to CHIP-8
+    const RETURN_REG: u8 = 4;
+
+    pub const fn new() -> Self {
+        let mut r = [0; 16];
+        r[2] = Self::STACK;
+
+        Self {
+            r,
+            d: 0,
+            df: false,
+            p: 0,
+            x: 2,
+            t: 0,
+            ie: true,
+            q: false,
+        }
+    }
+
+    /// Runs the subroutine at `addr` until it returns with `D4`.
+    pub fn call(&mut self, memory: &mut Memory, addr: u16) -> Result<()> {
+        self.r[Self::CALL_REG as usize] = addr;
+        self.p = Self::CALL_REG;
+        self.x = 2;
+
+        for _ in 0..Self::MAX_STEPS {
+            if self.step(memory)? {
+                return Ok(());
+            }
+        }
+
+        bail!(
+            "machine code subroutine at {addr:03x} did not return after {} instructions",
+            Self::MAX_STEPS
+        )
+    }
+
+    /// Executes a single instruction, returns `true` if it was the `SEP R4` returning to CHIP-8.
+    pub fn step(&mut self, memory: &mut Memory) -> Result<bool> {
+        let opcode = self.fetch(memory)?;
+        let (i, n) = (opcode >> 4, opcode & 0x0F);
+
+        match i {
+            // IDL, waits for an interrupt or DMA, neither of which ever comes. Zeroed memory is
+            // all IDLs, so this is usually a call into nowhere rather than a real wait.
+            0 if n == 0 => bail!(
+                "1802 IDL at {:03x} waits for an interrupt that never comes",
+                self.r[self.p as usize].wrapping_sub(1)
+            ),
+            // LDN
+            0 => self.d = memory.read_u8(self.r[n as usize])?,
+            // INC
+            1 => self.r[n as usize] = self.r[n as usize].wrapping_add(1),
+            // DEC
+            2 => self.r[n as usize] = self.r[n as usize].wrapping_sub(1),
+            // short branches
+            3 => {
+                let target = self.fetch(memory)?;
+
+                if self.condition(n) {
+                    let pc = &mut self.r[self.p as usize];
+                    *pc = (*pc & 0xFF00) | target as u16;
+                }
+            }
+            // LDA
+            4 => {
+                self.d = memory.read_u8(self.r[n as usize])?;
+                self.r[n as usize] = self.r[n as usize].wrapping_add(1);
+            }
+            // STR
+            5 => memory.write_u8(self.r[n as usize], self.d)?,
+            // IRX
+            6 if n == 0 => self.inc_x(),
+            // OUT, nothing is listening
+            6 if n < 8 => self.inc_x(),
+            // 1806 extended instructions are not part of the 1802
+            6 if n == 8 => bail!("unknown 1802 instruction: {opcode:02x}"),
+            // INP, the bus floats low
+            6 => {
+                self.d = 0;
+                memory.write_u8(self.rx(), 0)?;
+            }
+            7 => self.exec_7n(memory, n)?,
+            // GLO
+            8 => self.d = self.r[n as usize] as u8,
+            // GHI
+            9 => self.d = (self.r[n as usize] >> 8) as u8,
+            // PLO
+            0xA => self.r[n as usize] = (self.r[n as usize] & 0xFF00) | self.d as u16,
+            // PHI
+            0xB => self.r[n as usize] = (self.r[n as usize] & 0x00FF) | (self.d as u16) << 8,
+            0xC => self.exec_long(memory, n)?,
+            // SEP
+            0xD => {
+                self.p = n;
+
+                return Ok(n == Self::RETURN_REG);
+            }
+            // SEX
+            0xE => self.x = n,
+            0xF => self.exec_fn(memory, n)?,
+            _ => unreachable!(),
+        }
+
+        Ok(false)
+    }
+
+    fn exec_7n(&mut self, memory: &mut Memory, n: u8) -> Result<()> {
+        match n {
+            // RET, DIS
+            0 | 1 => {
+                let xp = memory.read_u8(self.rx())?;
+                self.inc_x();
+
+                (self.x, self.p) = (xp >> 4, xp & 0x0F);
+                self.ie = n == 0;
+            }
+            // LDXA
+            2 => {
+                self.d = memory.read_u8(self.rx())?;
+                self.inc_x();
+            }
+            // STXD
+            3 => {
+                memory.write_u8(self.rx(), self.d)?;
+                self.r[self.x as usize] = self.rx().wrapping_sub(1);
+            }
+            // ADC
+            4 => self.add(memory.read_u8(self.rx())?, self.df),
+            // SDB
+            5 => self.sub(memory.read_u8(self.rx())?, self.d, self.df),
+            // SHRC
+            6 => {
+                let carry = self.df as u8;
+
+                self.df = self.d & 1 != 0;
+                self.d = (self.d >> 1) | (carry << 7);
+            }
+            // SMB
+            7 => self.sub(self.d, memory.read_u8(self.rx())?, self.df),
+            // SAV
+            8 => memory.write_u8(self.rx(), self.t)?,
+            // MARK
+            9 => {
+                self.t = (self.x << 4) | self.p;
+                memory.write_u8(self.r[2], self.t)?;
+
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            // REQ, SEQ
+            0xA | 0xB => self.q = n == 0xB,
+            // ADCI
+            0xC => {
+                let imm = self.fetch(memory)?;
+                self.add(imm, self.df);
+            }
+            // SDBI
+            0xD => {
+                let imm = self.fetch(memory)?;
+                self.sub(imm, self.d, self.df);
+            }
+            // SHLC
+            0xE => {
+                let carry = self.df as u8;
+
+                self.df = self.d & 0x80 != 0;
+                self.d = (self.d << 1) | carry;
+            }
+            // SMBI
+            0xF => {
+                let imm = self.fetch(memory)?;
+                self.sub(self.d, imm, self.df);
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    fn exec_long(&mut self, memory: &Memory, n: u8) -> Result<()> {
+        let pc = self.r[self.p as usize];
+
+        match n {
+            // NOP
+            4 => {}
+            // long branches
+            0..=3 | 8..=0xB => {
+                if self.condition(n) {
+                    self.r[self.p as usize] = memory.read_u16(pc)?;
+                } else {
+                    self.r[self.p as usize] = pc.wrapping_add(2);
+                }
+            }
+            // long skips
+            _ => {
+                let skip = match n {
+                    0x5 => !self.q,
+                    0x6 => self.d != 0,
+                    0x7 => !self.df,
+                    0xC => self.ie,
+                    0xD => self.q,
+                    0xE => self.d == 0,
+                    0xF => self.df,
+                    _ => unreachable!(),
+                };
+
+                if skip {
+                    self.r[self.p as usize] = pc.wrapping_add(2);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn exec_fn(&mut self, memory: &mut Memory, n: u8) -> Result<()> {
+        // F0 - F7 operate on M(R(X)), F8 - FF on the immediate byte following the opcode
+        let operand = match n {
+            6 | 0xE => 0,
+            0..=7 => memory.read_u8(self.rx())?,
+            _ => self.fetch(memory)?,
+        };
+
+        match n & 0x7 {
+            // LDX, LDI
+            0 => self.d = operand,
+            // OR, ORI
+            1 => self.d |= operand,
+            // AND, ANI
+            2 => self.d &= operand,
+            // XOR, XRI
+            3 => self.d ^= operand,
+            // ADD, ADI
+            4 => self.add(operand, false),
+            // SD, SDI
+            5 => self.sub(operand, self.d, true),
+            // SHR
+            6 if n == 6 => {
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+            }
+            // SHL
+            6 => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            }
+            // SM, SMI
+            7 => self.sub(self.d, operand, true),
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    /// Condition for short (`3N`) and long (`CN`) branches, the high bit of N inverts it.
+    fn condition(&self, n: u8) -> bool {
+        let cond = match n & 0x7 {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            // EF1 - EF4, no flags are ever raised
+            _ => false,
+        };
+
+        cond ^ (n & 0x8 != 0)
+    }
+
+    fn fetch(&mut self, memory: &Memory) -> Result<u8> {
+        let pc = self.r[self.p as usize];
+        self.r[self.p as usize] = pc.wrapping_add(1);
+
+        memory.read_u8(pc)
+    }
+
+    fn add(&mut self, operand: u8, carry: bool) {
+        let result = self.d as u16 + operand as u16 + carry as u16;
+
+        self.d = result as u8;
+        self.df = result > 0xFF;
+    }
+
+    /// Computes `a - b`, DF is set when there was no borrow.
+    fn sub(&mut self, a: u8, b: u8, no_borrow: bool) {
+        let result = a as i16 - b as i16 - !no_borrow as i16;
+
+        self.d = result as u8;
+        self.df = result >= 0;
+    }
+
+    fn rx(&self) -> u16 {
+        self.r[self.x as usize]
+    }
+
+    fn inc_x(&mut self) {
+        self.r[self.x as usize] = self.rx().wrapping_add(1);
+    }
+}
+
+impl Default for Cdp1802 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(code: &[u8]) -> (Cdp1802, Memory) {
+        let mut memory = Memory::new(code);
+        let mut cpu = Cdp1802::new();
+
+        cpu.call(&mut memory, Memory::ROM_OFFSET).unwrap();
+
+        (cpu, memory)
+    }
+
+    #[test]
+    fn load_add_store() {
+        // LDI 0x0F, PLO RA, LDI 0x03, PHI RA, LDI 0xF0, ADI 0x20, STR RA, SEP R4
+        let (cpu, memory) = run(&[
+            0xF8, 0x0F, 0xAA, 0xF8, 0x03, 0xBA, 0xF8, 0xF0, 0xFC, 0x20, 0x5A, 0xD4,
+        ]);
+
+        assert_eq!(cpu.d, 0x10);
+        assert!(cpu.df);
+        assert_eq!(cpu.r[0xA], 0x030F);
+        assert_eq!(memory.read_u8(0x030F).unwrap(), 0x10);
+    }
+
+    #[test]
+    fn subtract_borrow() {
+        // LDI 0x05, SMI 0x06, SEP R4
+        let (cpu, _) = run(&[0xF8, 0x05, 0xFF, 0x06, 0xD4]);
+
+        assert_eq!(cpu.d, 0xFF);
+        assert!(!cpu.df);
+    }
+
+    #[test]
+    fn short_branch_loop() {
+        // LDI 0x03, PLO R5, (loop) DEC R5, GLO R5, BNZ loop, SEP R4
+        let (cpu, _) = run(&[0xF8, 0x03, 0xA5, 0x25, 0x85, 0x3A, 0x03, 0xD4]);
+
+        assert_eq!(cpu.r[5] & 0xFF, 0);
+        assert_eq!(cpu.d, 0);
+    }
+
+    #[test]
+    fn long_branch() {
+        // LBR 0x0206, IDL, IDL, SEQ, SEP R4
+        let (cpu, _) = run(&[0xC0, 0x02, 0x06, 0x00, 0x00, 0x00, 0x7B, 0xD4]);
+
+        assert!(cpu.q);
+    }
+
+    #[test]
+    fn idle_fails_fast() {
+        // LDI 0x01, IDL
+        let mut memory = Memory::new(&[0xF8, 0x01, 0x00]);
+        let mut cpu = Cdp1802::new();
+
+        let err = cpu.call(&mut memory, Memory::ROM_OFFSET).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1802 IDL at 202 waits for an interrupt that never comes"
+        );
+        assert_eq!(cpu.r[3], 0x203);
+    }
+
+    #[test]
+    fn runaway_routine() {
+        // BR 0x00
+        let mut memory = Memory::new(&[0x30, 0x00]);
+
+        assert!(Cdp1802::new()
+            .call(&mut memory, Memory::ROM_OFFSET)
+            .is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use std::{fmt, str::FromStr};
+
+use color_eyre::{eyre::eyre, Report, Result};
+
+use chirp_8::{machine::Machine, memory::RegIdx};
+
+use crate::debugger::parse_hex;
+
+/// Condition of a breakpoint, like `V3 == 10 && I > 300 || [2f0] != 0`. `&&` binds tighter
+/// than `||`, and numbers are hex like everywhere else in the debugger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    text: String,
+    /// Holds if all comparisons of any of these hold
+    any: Vec<Vec<Comparison>>,
+}
+
+impl Condition {
+    pub fn holds(&self, machine: &Machine) -> bool {
+        self.any
+            .iter()
+            .any(|all| all.iter().all(|comparison| comparison.holds(machine)))
+    }
+}
+
+impl FromStr for Condition {
+    type Err = Report;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let any = text
+            .split("||")
+            .map(|all| all.split("&&").map(str::parse).collect::<Result<_>>())
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            text: text.trim().to_owned(),
+            any,
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Comparison {
+    left: Operand,
+    op: Op,
+    right: Operand,
+}
+
+impl Comparison {
+    fn holds(&self, machine: &Machine) -> bool {
+        let (left, right) = (self.left.value(machine), self.right.value(machine));
+
+        match self.op {
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+            Op::Lt => left < right,
+            Op::Le => left <= right,
+            Op::Gt => left > right,
+            Op::Ge => left >= right,
+        }
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = Report;
+
+    fn from_str(text: &str) -> Result<Self> {
+        // two-character operators first, so `<=` isn't taken for `<`
+        const OPS: [(&str, Op); 6] = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ];
+
+        let (left, op, right) = OPS
+            .iter()
+            .find_map(|&(token, op)| {
+                let (left, right) = text.split_once(token)?;
+                Some((left, op, right))
+            })
+            .ok_or_else(|| eyre!("`{}` is not a comparison like `V3 == 10`", text.trim()))?;
+
+        Ok(Self {
+            left: left.parse()?,
+            op,
+            right: right.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Reg(RegIdx),
+    Index,
+    Pc,
+    DelayTimer,
+    SoundTimer,
+    /// The byte at an address, `[addr]`
+    Byte(u16),
+    Number(u16),
+}
+
+impl Operand {
+    fn value(self, machine: &Machine) -> u16 {
+        match self {
+            Operand::Reg(idx) => machine.regs[idx] as u16,
+            Operand::Index => machine.index,
+            Operand::Pc => machine.pc,
+            Operand::DelayTimer => machine.delay_timer as u16,
+            Operand::SoundTimer => machine.sound_timer as u16,
+            Operand::Byte(addr) => machine.memory.peek(addr).unwrap_or_default() as u16,
+            Operand::Number(value) => value,
+        }
+    }
+}
+
+impl FromStr for Operand {
+    type Err = Report;
+
+    fn from_str(word: &str) -> Result<Self> {
+        let word = word.trim();
+        if let Some(addr) = word
+            .strip_prefix('[')
+            .and_then(|word| word.strip_suffix(']'))
+        {
+            return Ok(Operand::Byte(parse_hex(addr.trim())?));
+        }
+
+        let operand = match word.to_ascii_lowercase().as_str() {
+            "i" => Operand::Index,
+            "pc" => Operand::Pc,
+            "dt" => Operand::DelayTimer,
+            "st" => Operand::SoundTimer,
+            reg if reg.len() == 2 && reg.starts_with('v') => {
+                let idx = u8::from_str_radix(&reg[1..], 16)
+                    .map_err(|_| eyre!("`{word}` is not a register"))?;
+                Operand::Reg(RegIdx::new(idx))
+            }
+            _ => Operand::Number(parse_hex(word)?),
+        };
+
+        Ok(operand)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chirp_8::machine::Quirks;
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let condition = "V3 == 0x10 && I > 300".parse::<Condition>().unwrap();
+        assert_eq!(
+            condition.any,
+            [[
+                Comparison {
+                    left: Operand::Reg(RegIdx::new(3)),
+                    op: Op::Eq,
+                    right: Operand::Number(0x10),
+                },
+                Comparison {
+                    left: Operand::Index,
+                    op: Op::Gt,
+                    right: Operand::Number(0x300),
+                },
+            ]]
+        );
+        assert_eq!(condition.to_string(), "V3 == 0x10 && I > 300");
+
+        let condition = "[2f0]<=dt || pc != 202".parse::<Condition>().unwrap();
+        assert_eq!(condition.any.len(), 2);
+        assert_eq!(condition.any[0][0].left, Operand::Byte(0x2F0));
+        assert_eq!(condition.any[0][0].op, Op::Le);
+
+        assert!("V3".parse::<Condition>().is_err());
+        assert!("V3 = 1".parse::<Condition>().is_err());
+        assert!("VG == 1".parse::<Condition>().is_err());
+        assert!("V3 == 1 &&".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn holds() {
+        let mut machine = Machine::new(&[0x63, 0x10], Quirks::default());
+        let condition = "V3 == 10 && [200] == 63 || st > 0"
+            .parse::<Condition>()
+            .unwrap();
+        assert!(!condition.holds(&machine));
+
+        machine.step().unwrap();
+        assert!(condition.holds(&machine));
+
+        machine.regs[RegIdx::new(3)] = 0;
+        machine.sound_timer = 1;
+        assert!(condition.holds(&machine));
+    }
+}
@@ -0,0 +1,280 @@
+use std::time::{Duration, Instant};
+
+use color_eyre::{eyre::eyre, Result};
+use sdl2::{
+    event::{Event, WindowEvent},
+    keyboard::Scancode,
+    pixels::Color,
+    rect::Rect,
+    render::Canvas,
+    video::Window,
+    Sdl,
+};
+
+use chirp_8::{
+    machine::Machine,
+    memory::{Memory, RegIdx},
+};
+
+use crate::{
+    debugger::{self, Debugger},
+    font,
+};
+
+/// Screen pixels per font pixel
+const SCALE: u32 = 2;
+/// Size of the window in characters
+const COLUMNS: u32 = 56;
+const ROWS: u32 = 38;
+/// Lines of disassembly, the current instruction is shown this many lines from the top
+const DISASM_LINES: u16 = 20;
+const DISASM_ABOVE: u16 = 6;
+/// Lines of 16 bytes in the memory view
+const MEMORY_LINES: u16 = 8;
+/// How often the view is redrawn at most
+const FRAME_PERIOD: Duration = Duration::from_millis(33);
+
+const BACKGROUND: Color = Color::RGB(0x16, 0x16, 0x1D);
+const TEXT: Color = Color::RGB(0xD0, 0xD0, 0xD0);
+const DIM: Color = Color::RGB(0x70, 0x70, 0x80);
+const HEADING: Color = Color::RGB(0x7F, 0xB4, 0xFF);
+/// Background of the current instruction
+const CURRENT: Color = Color::RGB(0x2D, 0x3F, 0x66);
+const BREAKPOINT: Color = Color::RGB(0xFF, 0x5F, 0x5F);
+/// Bytes written within the last second of emulated time
+const WRITTEN: Color = Color::RGB(0xFF, 0xB0, 0x3F);
+
+/// A second window showing the machine's state, F5 pauses and continues, F10 steps and
+/// PageUp/PageDown scroll the memory view (Home makes it follow I again).
+pub struct DebugView {
+    canvas: Canvas<Window>,
+    /// Cycle of the last write to every address
+    written_at: Box<[Option<u64>]>,
+    /// Instructions per second, how long written bytes stay colored
+    ips: u64,
+    /// First line of the memory view, `None` follows I
+    memory_top: Option<u16>,
+    last_draw: Option<Instant>,
+}
+
+impl DebugView {
+    pub fn new(context: &Sdl, ips: u64) -> Result<Self> {
+        let video = context
+            .video()
+            .map_err(|_| eyre!("failed to initialize video subsystem"))?;
+
+        let (cell_width, cell_height) = (font::CELL.0 * SCALE, font::CELL.1 * SCALE);
+        let window = video
+            .window("CHIRP-8 debugger", COLUMNS * cell_width, ROWS * cell_height)
+            .build()
+            .map_err(|_| eyre!("failed to create the debugger window"))?;
+
+        let canvas = window
+            .into_canvas()
+            .build()
+            .map_err(|_| eyre!("failed to create a canvas"))?;
+
+        Ok(Self {
+            canvas,
+            written_at: vec![None; Memory::default().as_bytes().len()].into_boxed_slice(),
+            ips,
+            memory_top: None,
+            last_draw: None,
+        })
+    }
+
+    /// Handles the hotkeys, returns whether the event closed the window.
+    pub fn handle(
+        &mut self,
+        event: &Event,
+        debugger: &mut Debugger,
+        machine: &mut Machine,
+    ) -> bool {
+        match event {
+            Event::Window {
+                window_id,
+                win_event: WindowEvent::Close,
+                ..
+            } => return *window_id == self.canvas.window().id(),
+            Event::KeyDown {
+                scancode: Some(scancode),
+                repeat,
+                ..
+            } => match scancode {
+                Scancode::F5 if !repeat => debugger.toggle_pause(machine),
+                // stepping repeats while the key is held
+                Scancode::F10 if debugger.paused() => debugger.step(machine),
+                Scancode::PageUp => self.scroll(machine, -(MEMORY_LINES as i32)),
+                Scancode::PageDown => self.scroll(machine, MEMORY_LINES as i32),
+                Scancode::Home => self.memory_top = None,
+                _ => return false,
+            },
+            _ => return false,
+        }
+
+        // show the change right away
+        self.last_draw = None;
+        false
+    }
+
+    /// Remembers which bytes the last instruction wrote, call after every instruction.
+    pub fn record(&mut self, machine: &mut Machine) {
+        if let Some((lo, hi)) = machine.memory.take_written() {
+            for written_at in &mut self.written_at[lo as usize..=hi as usize] {
+                *written_at = Some(machine.cycles);
+            }
+        }
+    }
+
+    /// Redraws the window, at most every [`FRAME_PERIOD`].
+    pub fn draw(&mut self, machine: &Machine, debugger: &Debugger) -> Result<()> {
+        if self
+            .last_draw
+            .is_some_and(|last| last.elapsed() < FRAME_PERIOD)
+        {
+            return Ok(());
+        }
+        self.last_draw = Some(Instant::now());
+
+        self.canvas.set_draw_color(BACKGROUND);
+        self.canvas.clear();
+
+        let status = match debugger.paused() {
+            true => "PAUSED   F5 continue  F10 step",
+            false => "RUNNING  F5 pause",
+        };
+        self.text(status, (0, 0), HEADING)?;
+
+        self.registers(machine, 2)?;
+        self.stack(machine, 7)?;
+        self.disassembly(machine, debugger, 7)?;
+        self.memory(machine, 7 + DISASM_LINES as u32 + 2)?;
+
+        self.canvas.present();
+
+        Ok(())
+    }
+
+    fn registers(&mut self, machine: &Machine, row: u32) -> Result<()> {
+        for idx in 0..16u8 {
+            let value = machine.regs[RegIdx::new(idx)];
+            let position = ((idx % 8) as u32 * 7, row + (idx / 8) as u32);
+
+            self.text(&format!("V{idx:X}"), position, DIM)?;
+            self.text(&format!("{value:02x}"), (position.0 + 3, position.1), TEXT)?;
+        }
+
+        let others = [
+            ("I", format!("{:03x}", machine.index)),
+            ("PC", format!("{:03x}", machine.pc)),
+            ("DT", format!("{:02x}", machine.delay_timer)),
+            ("ST", format!("{:02x}", machine.sound_timer)),
+        ];
+        for (idx, (name, value)) in others.iter().enumerate() {
+            let position = (idx as u32 * 7, row + 3);
+
+            self.text(name, position, DIM)?;
+            self.text(value, (position.0 + 3, position.1), TEXT)?;
+        }
+
+        Ok(())
+    }
+
+    fn stack(&mut self, machine: &Machine, row: u32) -> Result<()> {
+        self.text("STACK", (0, row), HEADING)?;
+
+        for (depth, addr) in machine.stack.iter().rev().enumerate() {
+            if depth as u32 >= DISASM_LINES as u32 {
+                break;
+            }
+
+            self.text(&format!("{addr:03x}"), (0, row + 1 + depth as u32), TEXT)?;
+        }
+
+        Ok(())
+    }
+
+    fn disassembly(&mut self, machine: &Machine, debugger: &Debugger, row: u32) -> Result<()> {
+        const COLUMN: u32 = 8;
+        self.text("DISASSEMBLY", (COLUMN, row), HEADING)?;
+
+        let start = machine.pc.saturating_sub(DISASM_ABOVE * 2);
+        for line in 0..DISASM_LINES {
+            let addr = start + line * 2;
+            let position = (COLUMN, row + 1 + line as u32);
+
+            if addr == machine.pc {
+                let rect = self.cells(position, COLUMNS - COLUMN);
+                self.canvas.set_draw_color(CURRENT);
+                self.canvas
+                    .fill_rect(rect)
+                    .map_err(|_| eyre!("failed to draw the debugger"))?;
+            }
+            if debugger.breakpoints().contains_key(&addr) {
+                self.text("*", position, BREAKPOINT)?;
+            }
+
+            // without the arrow marking the current instruction, it's highlighted instead
+            let text = debugger::disassemble(machine, addr);
+            self.text(&text[2..], position, TEXT)?;
+        }
+
+        Ok(())
+    }
+
+    fn memory(&mut self, machine: &Machine, row: u32) -> Result<()> {
+        self.text("MEMORY", (0, row), HEADING)?;
+
+        let bytes = machine.memory.as_bytes();
+        let top = self.memory_top.unwrap_or(machine.index & !0xF);
+        let recent = machine.cycles.saturating_sub(self.ips);
+
+        for line in 0..MEMORY_LINES {
+            let addr = top as usize + line as usize * 16;
+            if addr >= bytes.len() {
+                break;
+            }
+            let line_row = row + 1 + line as u32;
+
+            self.text(&format!("{addr:03x}"), (0, line_row), DIM)?;
+            for (offset, byte) in bytes[addr..(addr + 16).min(bytes.len())].iter().enumerate() {
+                let color = match self.written_at[addr + offset] {
+                    Some(cycle) if cycle >= recent => WRITTEN,
+                    _ => TEXT,
+                };
+                self.text(
+                    &format!("{byte:02x}"),
+                    (5 + offset as u32 * 3, line_row),
+                    color,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scroll(&mut self, machine: &Machine, lines: i32) {
+        let top = self.memory_top.unwrap_or(machine.index & !0xF) as i32;
+        let last = machine.memory.as_bytes().len() as i32 - MEMORY_LINES as i32 * 16;
+
+        self.memory_top = Some((top + lines * 16).clamp(0, last) as u16);
+    }
+
+    /// Rectangle of `count` character cells starting at `(column, row)`
+    fn cells(&self, (column, row): (u32, u32), count: u32) -> Rect {
+        let (width, height) = (font::CELL.0 * SCALE, font::CELL.1 * SCALE);
+
+        Rect::new(
+            (column * width) as i32,
+            (row * height) as i32,
+            count * width,
+            height,
+        )
+    }
+
+    fn text(&mut self, text: &str, position: (u32, u32), color: Color) -> Result<()> {
+        let cell = self.cells(position, 1);
+
+        font::draw(&mut self.canvas, text, (cell.x(), cell.y()), SCALE, color)
+    }
+}
@@ -0,0 +1,593 @@
+use std::{
+    collections::BTreeMap,
+    io::Write as _,
+    sync::mpsc::{self, Receiver, TryRecvError},
+};
+
+use color_eyre::{
+    eyre::{bail, eyre},
+    Report, Result,
+};
+
+use chirp_8::{
+    instruction::Instruction,
+    machine::Machine,
+    memory::{RegIdx, Watchpoint},
+};
+
+use crate::{
+    condition::Condition,
+    run_control::{RunControl, Stop},
+};
+
+/// Instructions shown by `disasm` without a count
+const DISASM_LEN: u16 = 10;
+
+const HELP: &str = "\
+step [n]             execute one or n instructions, n is decimal
+continue             run until a breakpoint or an error
+break <addr> [if c]  stop before executing the instruction at addr, if c holds
+watch <addr> [len]   stop after an instruction writes len bytes at addr, 1 by default
+rwatch <addr> [len]  stop after an instruction reads them
+awatch <addr> [len]  stop after an instruction reads or writes them
+list                 show the breakpoints and watchpoints
+delete [addr]        remove the breakpoint and watchpoints at addr, or all of them
+regs                 show V0 - VF, I, PC and the timers
+stack                show the call stack, innermost first
+mem <addr> <len>     dump len bytes of memory starting at addr
+disasm [addr] [n]    disassemble n instructions starting at addr, PC by default
+help                 show this message
+Other numbers are hex, with or without 0x. Conditions compare V0 - VF, I,
+PC, DT, ST, bytes of memory like [2f0] and numbers with == != < <= > >=, and
+combine them with && and ||, e.g. `break 2a0 if V3 == 10 && I > 300`.";
+
+/// A command typed at the debugger prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    Step(u32),
+    Continue,
+    Break(u16, Option<Condition>),
+    Watch(Watchpoint),
+    List,
+    /// Deletes the breakpoint and watchpoints at an address, or all of them
+    Delete(Option<u16>),
+    Regs,
+    Stack,
+    Mem(u16, u16),
+    Disasm(Option<u16>, u16),
+    Help,
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Self> {
+        let (line, condition) = match line.split_once(" if ") {
+            Some((line, condition)) => (line, Some(condition.parse()?)),
+            None => (line, None),
+        };
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args = match name {
+            "step" | "s" => words.map(parse_decimal).collect::<Result<Vec<_>>>()?,
+            _ => words.map(parse_hex).collect::<Result<Vec<_>>>()?,
+        };
+
+        let mut condition = condition;
+        let command = match (name, args.as_slice()) {
+            ("step" | "s", []) => Command::Step(1),
+            ("step" | "s", &[count]) => Command::Step(count as u32),
+            ("continue" | "c", []) => Command::Continue,
+            ("break" | "b", &[addr]) => Command::Break(addr, condition.take()),
+            ("watch" | "w" | "rwatch" | "awatch", &[addr]) => watch(name, addr, 1)?,
+            ("watch" | "w" | "rwatch" | "awatch", &[addr, len]) => watch(name, addr, len)?,
+            ("list" | "l", []) => Command::List,
+            ("delete" | "d", []) => Command::Delete(None),
+            ("delete" | "d", &[addr]) => Command::Delete(Some(addr)),
+            ("regs" | "r", []) => Command::Regs,
+            ("stack", []) => Command::Stack,
+            ("mem" | "m", &[addr, len]) => Command::Mem(addr, len),
+            ("disasm" | "x", []) => Command::Disasm(None, DISASM_LEN),
+            ("disasm" | "x", &[addr]) => Command::Disasm(Some(addr), DISASM_LEN),
+            ("disasm" | "x", &[addr, count]) => Command::Disasm(Some(addr), count),
+            ("help" | "h", []) => Command::Help,
+            (
+                "step" | "s" | "continue" | "c" | "break" | "b" | "watch" | "w" | "rwatch"
+                | "awatch" | "list" | "l" | "delete" | "d" | "regs" | "r" | "stack" | "mem" | "m"
+                | "disasm" | "x" | "help" | "h",
+                _,
+            ) => bail!("wrong arguments for `{name}`, see `help`"),
+            _ => bail!("unknown command `{name}`, see `help`"),
+        };
+        if condition.is_some() {
+            bail!("only `break` takes a condition");
+        }
+
+        Ok(command)
+    }
+}
+
+/// The watchpoint set by one of the `watch` commands
+fn watch(name: &str, start: u16, len: u16) -> Result<Command> {
+    let end = len
+        .checked_sub(1)
+        .and_then(|len| start.checked_add(len))
+        .ok_or_else(|| eyre!("can't watch {len:#x} bytes at {start:#05x}"))?;
+
+    Ok(Command::Watch(Watchpoint {
+        start,
+        end,
+        read: name != "watch" && name != "w",
+        write: name != "rwatch",
+    }))
+}
+
+pub fn parse_hex(word: &str) -> Result<u16> {
+    let digits = word.strip_prefix("0x").unwrap_or(word);
+
+    u16::from_str_radix(digits, 16).map_err(|_| eyre!("`{word}` is not a hex number"))
+}
+
+fn parse_decimal(word: &str) -> Result<u16> {
+    word.parse()
+        .map_err(|_| eyre!("`{word}` is not a decimal number"))
+}
+
+/// Debugger reading commands from stdin, and controlled by the debug view's hotkeys. Lines
+/// are read on another thread, so the window keeps responding while the machine is paused.
+pub struct Debugger {
+    control: RunControl,
+    /// `None` without the command line
+    lines: Option<Receiver<String>>,
+    /// Whether stdin was closed, then the machine just runs
+    closed: bool,
+}
+
+impl Debugger {
+    /// Starts paused before the first instruction, reading commands from stdin if `repl`.
+    pub fn new(machine: &Machine, repl: bool) -> Self {
+        if !repl {
+            return Self::with_lines(None);
+        }
+
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        println!("paused on entry, type `help` for commands");
+        let debugger = Self::with_lines(Some(lines));
+        debugger.pause_at(machine);
+
+        debugger
+    }
+
+    fn with_lines(lines: Option<Receiver<String>>) -> Self {
+        Self {
+            // only the command line pauses on entry, there's a prompt to continue from
+            control: RunControl::new(lines.is_some()),
+            lines,
+            closed: false,
+        }
+    }
+
+    /// Called before every instruction, returns whether it should be executed. Commands are
+    /// handled here while paused.
+    pub fn poll(&mut self, machine: &mut Machine) -> bool {
+        match self.control.before_step(machine) {
+            Some(Stop::Breakpoint) => {
+                match &self.control.breakpoints()[&machine.pc] {
+                    Some(condition) => {
+                        println!("breakpoint at {:#05x} ({condition})", machine.pc)
+                    }
+                    None => println!("breakpoint at {:#05x}", machine.pc),
+                }
+                self.pause_at(machine);
+            }
+            Some(_) => self.pause_at(machine),
+            None if !self.control.paused() => return true,
+            None => {}
+        }
+
+        loop {
+            let line = match self.lines.as_ref().map(Receiver::try_recv) {
+                Some(Ok(line)) => line,
+                None | Some(Err(TryRecvError::Empty)) => return false,
+                // nobody can type commands anymore
+                Some(Err(TryRecvError::Disconnected)) => {
+                    self.closed = true;
+                    self.control.resume(machine);
+                    return self.poll(machine);
+                }
+            };
+
+            if line.trim().is_empty() {
+                prompt();
+                continue;
+            }
+
+            match Command::parse(&line) {
+                Ok(command) => self.run(command, machine),
+                Err(err) => {
+                    println!("error: {err}");
+                    prompt();
+                }
+            }
+
+            if !self.control.paused() {
+                return self.poll(machine);
+            }
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.control.paused()
+    }
+
+    pub fn breakpoints(&self) -> &BTreeMap<u16, Option<Condition>> {
+        self.control.breakpoints()
+    }
+
+    /// Called after every instruction, pauses if the one at `pc` accessed a watched address.
+    pub fn after_step(&mut self, machine: &mut Machine, pc: u16) {
+        let Some(Stop::Watch { access, pc }) = self.control.after_step(machine, pc) else {
+            return;
+        };
+
+        let kind = if access.write {
+            "write to"
+        } else {
+            "read from"
+        };
+        println!(
+            "{kind} {:#05x} by the instruction at {pc:#05x}",
+            access.addr
+        );
+        self.pause_at(machine);
+    }
+
+    /// Pauses a running machine, or continues a paused one.
+    pub fn toggle_pause(&mut self, machine: &mut Machine) {
+        match self.control.paused() {
+            true => self.run(Command::Continue, machine),
+            false => {
+                self.control.pause();
+                self.pause_at(machine);
+            }
+        }
+    }
+
+    /// Executes one instruction and pauses again.
+    pub fn step(&mut self, machine: &mut Machine) {
+        self.run(Command::Step(1), machine);
+    }
+
+    /// Pauses on an error instead of quitting, `machine.pc` should point at the instruction
+    /// that failed. Without stdin there's nobody to look into it, so the error is returned.
+    pub fn error(&mut self, machine: &Machine, err: Report) -> Result<()> {
+        if self.closed {
+            return Err(err);
+        }
+
+        println!("error: {err}");
+        self.control.pause();
+        self.pause_at(machine);
+
+        Ok(())
+    }
+
+    fn run(&mut self, command: Command, machine: &mut Machine) {
+        match command {
+            Command::Step(count) => return self.control.step(count),
+            Command::Continue => return self.control.resume(machine),
+            Command::Break(addr, condition) => {
+                match &condition {
+                    Some(condition) => println!("breakpoint at {addr:#05x} if {condition}"),
+                    None => println!("breakpoint at {addr:#05x}"),
+                }
+                self.control.breakpoints_mut().insert(addr, condition);
+            }
+            Command::Watch(watchpoint) => {
+                println!("{}", describe(&watchpoint));
+                machine.memory.watchpoints.push(watchpoint);
+            }
+            Command::List => print!("{}", self.list(machine)),
+            Command::Delete(Some(addr)) => {
+                let watchpoints = &mut machine.memory.watchpoints;
+                let count = watchpoints.len();
+                watchpoints.retain(|watch| !(watch.start..=watch.end).contains(&addr));
+
+                let breakpoint = self.control.breakpoints_mut().remove(&addr);
+                if breakpoint.is_none() && watchpoints.len() == count {
+                    println!("no breakpoint or watchpoint at {addr:#05x}");
+                }
+            }
+            Command::Delete(None) => {
+                self.control.breakpoints_mut().clear();
+                machine.memory.watchpoints.clear();
+            }
+            Command::Regs => print!("{}", regs(machine)),
+            Command::Stack => {
+                for (depth, addr) in machine.stack.iter().rev().enumerate() {
+                    println!("#{depth} {addr:#05x}");
+                }
+                if machine.stack.is_empty() {
+                    println!("the stack is empty");
+                }
+            }
+            Command::Mem(addr, len) => print!("{}", hexdump(machine, addr, len)),
+            Command::Disasm(addr, count) => {
+                let addr = addr.unwrap_or(machine.pc);
+                for idx in 0..count {
+                    println!("{}", disassemble(machine, addr.wrapping_add(idx * 2)));
+                }
+            }
+            Command::Help => println!("{HELP}"),
+        }
+
+        prompt();
+    }
+
+    fn list(&self, machine: &Machine) -> String {
+        let mut out = String::new();
+
+        for (addr, condition) in self.control.breakpoints() {
+            out += &match condition {
+                Some(condition) => format!("breakpoint at {addr:#05x} if {condition}\n"),
+                None => format!("breakpoint at {addr:#05x}\n"),
+            };
+        }
+        for watchpoint in &machine.memory.watchpoints {
+            out += &(describe(watchpoint) + "\n");
+        }
+        if out.is_empty() {
+            out += "no breakpoints or watchpoints\n";
+        }
+
+        out
+    }
+
+    fn pause_at(&self, machine: &Machine) {
+        if self.lines.is_some() {
+            println!("{}", disassemble(machine, machine.pc));
+            prompt();
+        }
+    }
+}
+
+fn describe(watchpoint: &Watchpoint) -> String {
+    let kind = match (watchpoint.read, watchpoint.write) {
+        (true, true) => "access",
+        (true, false) => "read",
+        _ => "write",
+    };
+
+    match watchpoint.start == watchpoint.end {
+        true => format!("{kind} watchpoint at {:#05x}", watchpoint.start),
+        false => format!(
+            "{kind} watchpoint at {:#05x} - {:#05x}",
+            watchpoint.start, watchpoint.end
+        ),
+    }
+}
+
+fn prompt() {
+    print!("(chirp-8) ");
+    let _ = std::io::stdout().flush();
+}
+
+fn regs(machine: &Machine) -> String {
+    let mut out = String::new();
+
+    for row in 0..2 {
+        let regs = (0..8)
+            .map(|col| {
+                let idx = RegIdx::new(row * 8 + col);
+                format!("V{:X} {:02x}", idx.as_u8(), machine.regs[idx])
+            })
+            .collect::<Vec<_>>();
+        out += &(regs.join("  ") + "\n");
+    }
+
+    out + &format!(
+        "I {:03x}  PC {:03x}  DT {:02x}  ST {:02x}\n",
+        machine.index, machine.pc, machine.delay_timer, machine.sound_timer
+    )
+}
+
+/// 16 bytes per line, stopping at the end of memory
+fn hexdump(machine: &Machine, addr: u16, len: u16) -> String {
+    let mut out = String::new();
+    for (line, chunk) in machine.memory.peek_slice(addr, len).chunks(16).enumerate() {
+        let hex = chunk
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>();
+        out += &format!("{:03x}: {}\n", addr as usize + line * 16, hex.join(" "));
+    }
+
+    out
+}
+
+/// One line of disassembly, the instruction at `pc` is marked with an arrow
+pub fn disassemble(machine: &Machine, addr: u16) -> String {
+    let marker = if addr == machine.pc { "=>" } else { "  " };
+
+    let memory = &machine.memory;
+    let lo = addr.checked_add(1).and_then(|addr| memory.peek(addr));
+    match (memory.peek(addr), lo) {
+        (Some(hi), Some(lo)) => {
+            let word = u16::from_be_bytes([hi, lo]);
+            match Instruction::decode(word) {
+                Some(inst) => format!("{marker} {addr:03x}: {word:04x}  {inst}"),
+                None => format!("{marker} {addr:03x}: {word:04x}  (unknown)"),
+            }
+        }
+        _ => format!("{marker} {addr:03x}: out of memory"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Sender;
+
+    use chirp_8::machine::Quirks;
+
+    use super::*;
+
+    /// A debugger fed from the returned sender, with `V0 += 1` and a jump back in memory
+    fn debugger() -> (Debugger, Sender<String>, Machine) {
+        let (sender, lines) = mpsc::channel();
+        let machine = Machine::new(&[0x70, 0x01, 0x12, 0x00], Quirks::default());
+
+        (Debugger::with_lines(Some(lines)), sender, machine)
+    }
+
+    /// Runs the machine for `count` polls, like the emulator loop does
+    fn run(debugger: &mut Debugger, machine: &mut Machine, count: usize) {
+        for _ in 0..count {
+            if debugger.poll(machine) {
+                let pc = machine.pc;
+                machine.step().unwrap();
+                debugger.after_step(machine, pc);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Command::parse("step").unwrap(), Command::Step(1));
+        assert_eq!(Command::parse("s 10").unwrap(), Command::Step(10));
+        assert_eq!(
+            Command::parse("break 0x2a0").unwrap(),
+            Command::Break(0x2A0, None)
+        );
+        assert_eq!(
+            Command::parse("b 202 if V0 == 3").unwrap(),
+            Command::Break(0x202, Some("V0 == 3".parse().unwrap()))
+        );
+        assert_eq!(
+            Command::parse("rwatch 300 3").unwrap(),
+            Command::Watch(Watchpoint {
+                start: 0x300,
+                end: 0x302,
+                read: true,
+                write: false
+            })
+        );
+        assert_eq!(
+            Command::parse("awatch 300").unwrap(),
+            Command::Watch(Watchpoint {
+                start: 0x300,
+                end: 0x300,
+                read: true,
+                write: true
+            })
+        );
+        assert_eq!(Command::parse("delete").unwrap(), Command::Delete(None));
+        assert_eq!(
+            Command::parse("mem 300 20").unwrap(),
+            Command::Mem(0x300, 0x20)
+        );
+        assert_eq!(
+            Command::parse("disasm").unwrap(),
+            Command::Disasm(None, DISASM_LEN)
+        );
+
+        assert!(Command::parse("break").is_err());
+        assert!(Command::parse("break 202 if V0").is_err());
+        assert!(Command::parse("step if V0 == 3").is_err());
+        assert!(Command::parse("step 0x10").is_err());
+        assert!(Command::parse("watch 300 0").is_err());
+        assert!(Command::parse("mem 300 zz").is_err());
+        assert!(Command::parse("frobnicate").is_err());
+    }
+
+    #[test]
+    fn paused_until_told_otherwise() {
+        let (mut debugger, sender, mut machine) = debugger();
+
+        run(&mut debugger, &mut machine, 10);
+        assert_eq!(machine.cycles, 0);
+
+        sender.send("step 3".into()).unwrap();
+        run(&mut debugger, &mut machine, 10);
+        assert_eq!(machine.cycles, 3);
+    }
+
+    #[test]
+    fn runs_without_the_command_line() {
+        let (_, _, mut machine) = debugger();
+        let mut debugger = Debugger::new(&machine, false);
+
+        run(&mut debugger, &mut machine, 10);
+        assert_eq!(machine.cycles, 10);
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let (mut debugger, sender, mut machine) = debugger();
+
+        sender.send("break 202".into()).unwrap();
+        sender.send("continue".into()).unwrap();
+        run(&mut debugger, &mut machine, 10);
+        assert_eq!((machine.pc, machine.cycles), (0x202, 1));
+
+        // continuing leaves the breakpoint before stopping at it again
+        sender.send("c".into()).unwrap();
+        run(&mut debugger, &mut machine, 10);
+        assert_eq!((machine.pc, machine.cycles), (0x202, 3));
+
+        sender.send("delete 202".into()).unwrap();
+        sender.send("c".into()).unwrap();
+        run(&mut debugger, &mut machine, 10);
+        assert_eq!(machine.cycles, 13);
+    }
+
+    #[test]
+    fn conditional_breakpoints() {
+        let (mut debugger, sender, mut machine) = debugger();
+
+        sender.send("break 202 if V0 == 3".into()).unwrap();
+        sender.send("continue".into()).unwrap();
+        run(&mut debugger, &mut machine, 20);
+        assert_eq!((machine.pc, machine.cycles), (0x202, 5));
+    }
+
+    #[test]
+    fn stops_at_watchpoints() {
+        // I = 300, BCD of V0 at I, V0 += 1, jump back to the BCD
+        let (mut debugger, sender, _) = debugger();
+        let mut machine = Machine::new(
+            &[0xA3, 0x00, 0xF0, 0x33, 0x70, 0x01, 0x12, 0x02],
+            Quirks::default(),
+        );
+
+        // BCD writes the ones digit to 302
+        sender.send("watch 302".into()).unwrap();
+        sender.send("continue".into()).unwrap();
+        run(&mut debugger, &mut machine, 20);
+        assert_eq!((machine.pc, machine.cycles), (0x204, 2));
+
+        // reads by instruction fetches don't count
+        sender.send("delete".into()).unwrap();
+        sender.send("rwatch 200 8".into()).unwrap();
+        sender.send("continue".into()).unwrap();
+        run(&mut debugger, &mut machine, 20);
+        assert_eq!(machine.cycles, 22);
+        assert!(!debugger.paused());
+    }
+
+    #[test]
+    fn dumps() {
+        let (_, _, mut machine) = debugger();
+        machine.step().unwrap();
+
+        assert!(regs(&machine).starts_with("V0 01  V1 00"));
+        assert_eq!(hexdump(&machine, 0x200, 4), "200: 70 01 12 00\n");
+        assert_eq!(disassemble(&machine, 0x202), "=> 202: 1200  JP 0x200");
+    }
+}
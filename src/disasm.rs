@@ -0,0 +1,128 @@
+use std::{collections::BTreeMap, fmt::Write as _};
+
+use crate::{
+    instruction::{Instruction, Syntax},
+    memory::Memory,
+};
+
+/// Disassembles a ROM loaded at [`Memory::ROM_OFFSET`], a line per word with its address, the
+/// raw word and the instruction in `syntax`.
+///
+/// Words are decoded in a single sweep from the start. Targets of jumps and calls inside the
+/// ROM get a label (`sub_XXX` for subroutines, `loc_XXX` otherwise), and words that don't
+/// decode are written as data, like an odd byte at the end.
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> String {
+    let words = rom
+        .chunks(2)
+        .enumerate()
+        .map(|(idx, bytes)| (Memory::ROM_OFFSET + idx as u16 * 2, bytes))
+        .collect::<Vec<_>>();
+    let labels = labels(&words);
+    let mut out = String::new();
+
+    for &(addr, bytes) in &words {
+        if let Some(label) = labels.get(&addr) {
+            match syntax {
+                Syntax::Octo => writeln!(out, ": {label}"),
+                Syntax::Cowgod | Syntax::Mnemonic => writeln!(out, "{label}:"),
+            }
+            .unwrap();
+        }
+
+        let raw = bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+        let text = match *bytes {
+            [hi, lo] => match Instruction::decode(u16::from_be_bytes([hi, lo])) {
+                Some(inst) => inst.syntax(syntax).with_labels(&labels).to_string(),
+                None => data(bytes, syntax),
+            },
+            _ => data(bytes, syntax),
+        };
+        writeln!(out, "{addr:03x}: {raw:<4}  {text}").unwrap();
+    }
+
+    out
+}
+
+/// Names for the targets of jumps and calls that are the start of a word
+fn labels(words: &[(u16, &[u8])]) -> BTreeMap<u16, String> {
+    let starts = words.iter().map(|&(addr, _)| addr).collect::<Vec<_>>();
+    let mut labels = BTreeMap::new();
+
+    for &(_, bytes) in words {
+        let &[hi, lo] = bytes else { continue };
+
+        match Instruction::decode(u16::from_be_bytes([hi, lo])) {
+            Some(Instruction::Call(addr)) if starts.binary_search(&addr).is_ok() => {
+                labels.insert(addr, format!("sub_{addr:03x}"));
+            }
+            Some(Instruction::Jump(addr)) if starts.binary_search(&addr).is_ok() => {
+                // a subroutine keeps its name when it's also jumped to
+                labels
+                    .entry(addr)
+                    .or_insert_with(|| format!("loc_{addr:03x}"));
+            }
+            _ => {}
+        }
+    }
+
+    labels
+}
+
+fn data(bytes: &[u8], syntax: Syntax) -> String {
+    let hex = bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+
+    match (syntax, bytes.len()) {
+        (Syntax::Octo, _) => bytes
+            .iter()
+            .map(|b| format!("{b:#04x}"))
+            .collect::<Vec<_>>()
+            .join(" "),
+        (Syntax::Cowgod, 1) => format!("DB 0x{hex}"),
+        (Syntax::Cowgod, _) => format!("DW 0x{hex}"),
+        (Syntax::Mnemonic, _) => format!("Data 0x{hex}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Calls 206, jumps back to the start, a word that doesn't decode, the subroutine
+    /// returning and a stray byte
+    const ROM: &[u8] = &[0x22, 0x06, 0x12, 0x00, 0xFF, 0xFF, 0x00, 0xEE, 0xAB];
+
+    #[test]
+    fn cowgod() {
+        assert_eq!(
+            disassemble(ROM, Syntax::Cowgod),
+            "loc_200:\n\
+             200: 2206  CALL sub_206\n\
+             202: 1200  JP loc_200\n\
+             204: ffff  DW 0xffff\n\
+             sub_206:\n\
+             206: 00ee  RET\n\
+             208: ab    DB 0xab\n"
+        );
+    }
+
+    #[test]
+    fn octo() {
+        assert_eq!(
+            disassemble(ROM, Syntax::Octo),
+            ": loc_200\n\
+             200: 2206  :call sub_206\n\
+             202: 1200  jump loc_200\n\
+             204: ffff  0xff 0xff\n\
+             : sub_206\n\
+             206: 00ee  return\n\
+             208: ab    0xab\n"
+        );
+    }
+
+    #[test]
+    fn targets_outside_the_rom_stay_addresses() {
+        let listing = disassemble(&[0x13, 0x00, 0x22, 0x01], Syntax::Mnemonic);
+
+        assert_eq!(listing, "200: 1300  Jump 0x300\n202: 2201  Call 0x201\n");
+    }
+}
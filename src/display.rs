@@ -1,79 +1,491 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
 use color_eyre::{eyre::eyre, Result};
 
-use sdl2::{pixels::Color, rect::Rect, render::Canvas, video::Window, Sdl};
+use chirp_8::machine::{Framebuffer, HEIGHT, WIDTH};
+use clap::ValueEnum;
+use sdl2::{
+    pixels::PixelFormatEnum,
+    rect::Rect,
+    render::{Canvas, Texture},
+    video::{FullscreenType, Window, WindowPos},
+    Sdl,
+};
+
+use crate::{
+    filter::{Filter, Image},
+    geometry::Geometry,
+    keypad::{self, Placement},
+    palette::{mix, Palette},
+    screenshot, Args, DEFAULT_SCALE,
+};
+
+/// Bytes per pixel of the RGB24 texture
+const BPP: usize = 3;
+/// Bytes per row of the RGB24 texture
+const PITCH: usize = WIDTH as usize * BPP;
+
+/// How pixels that were just turned off are shown, hiding the flicker of XOR sprites
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Persistence {
+    /// Pixels go dark immediately
+    #[default]
+    None,
+    /// Pixels fade out like a phosphor screen over `--decay-frames` refreshes
+    Decay,
+    /// Every refresh shows the average of the last two framebuffers
+    Blend,
+}
 
 pub struct Display {
     canvas: Canvas<Window>,
-    scale: u32,
-    pub buffer: [[bool; Self::HEIGHT as usize]; Self::WIDTH as usize],
+    /// Streaming texture holding the filtered image, the renderer scales it to the window
+    texture: Texture,
+    renderer: Renderer,
+    filter: Filter,
+    /// Only scale by whole numbers, leaving a wider border around the image
+    integer_scaling: bool,
+    /// Where the on-screen keypad is drawn, if it is shown
+    keypad: Option<Placement>,
+    /// Held keys, highlighted on the keypad
+    keys: [bool; 16],
+    /// Window geometry before switching to fullscreen
+    windowed: Option<Geometry>,
+    /// Latest framebuffer from the machine
+    current: Framebuffer,
+    /// Rendered pixels currently in the texture, before filtering
+    uploaded: Option<Image>,
+    refresh_period: Duration,
+    last_present: Instant,
 }
 
 impl Display {
-    pub const WIDTH: u8 = 64;
-    pub const HEIGHT: u8 = 32;
-
-    pub fn new(context: &Sdl, scale: u32) -> Result<Self> {
+    pub fn new(context: &Sdl, args: &Args, palette: Palette) -> Result<Self> {
         let video = context
             .video()
             .map_err(|_| eyre!("failed to initialize video subsystem"))?;
 
-        let window = video
-            .window(
-                "CHIRP-8",
-                Self::WIDTH as u32 * scale,
-                Self::HEIGHT as u32 * scale,
-            )
-            // .opengl()
-            .position_centered()
+        // an explicit scale wins over the size remembered from the last run
+        let geometry = match args.scale {
+            Some(_) => None,
+            None => Geometry::load(),
+        };
+        let scale = args.scale.unwrap_or(DEFAULT_SCALE);
+        let game = (WIDTH as u32 * scale, HEIGHT as u32 * scale);
+        let (width, height) = match (geometry, args.keypad) {
+            (Some(g), _) => (g.width, g.height),
+            (None, Some(keypad)) => keypad.window_size(game),
+            (None, None) => game,
+        };
+
+        let mut builder = video.window("CHIRP-8", width, height);
+        match geometry {
+            Some(g) => builder.position(g.x, g.y),
+            None => builder.position_centered(),
+        };
+        let window = builder
+            .resizable()
             .build()
             .map_err(|_| eyre!("failed to create a window"))?;
 
+        // fall back to 60 Hz if the display doesn't report its refresh rate
+        let refresh_rate = window
+            .display_mode()
+            .ok()
+            .map(|mode| mode.refresh_rate)
+            .filter(|&rate| rate > 0)
+            .unwrap_or(60);
+
         let mut canvas = window
             .into_canvas()
             .build()
             .map_err(|_| eyre!("failed to create a canvas"))?;
 
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        let texture = canvas
+            .texture_creator()
+            .create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)
+            .map_err(|_| eyre!("failed to create a texture"))?;
+
+        canvas.set_draw_color(palette.background());
         canvas.clear();
         canvas.present();
 
         Ok(Self {
             canvas,
-            scale,
-            buffer: [[false; Self::HEIGHT as usize]; Self::WIDTH as usize],
+            texture,
+            renderer: Renderer::new(palette, args.persistence, args.decay_frames),
+            filter: args.filter,
+            integer_scaling: args.integer_scaling,
+            keypad: args.keypad,
+            keys: [false; 16],
+            windowed: None,
+            current: [[false; HEIGHT as usize]; WIDTH as usize],
+            uploaded: None,
+            refresh_period: Duration::from_secs(1) / refresh_rate as u32,
+            last_present: Instant::now(),
         })
     }
 
-    pub fn clear(&mut self) -> Result<()> {
-        self.buffer = [[false; Self::HEIGHT as usize]; Self::WIDTH as usize];
+    pub fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    /// Takes the framebuffer to show on the next refresh.
+    pub fn update(&mut self, buffer: &Framebuffer) -> Result<()> {
+        self.current = *buffer;
+
+        Ok(())
+    }
+
+    /// Presents the latest framebuffer, at most once per display refresh. The texture is only
+    /// re-uploaded if the rendered pixels changed.
+    pub fn present(&mut self) -> Result<()> {
+        if self.last_present.elapsed() < self.refresh_period {
+            return Ok(());
+        }
+
+        let image = self.renderer.render(&self.current);
+        if self.uploaded.as_ref() != Some(&image) {
+            self.upload(&image)?;
+            self.uploaded = Some(image);
+        }
+
+        // the area outside of the image is filled with the background color
+        self.canvas
+            .set_draw_color(self.renderer.palette.background());
+        self.canvas.clear();
+        self.canvas
+            .copy(&self.texture, None, self.viewport())
+            .map_err(|_| eyre!("failed to draw"))?;
+        if let Some(placement) = self.keypad {
+            let (_, area) = placement.split(self.output_size());
+            keypad::draw(&mut self.canvas, area, &self.keys, &self.renderer.palette)?;
+        }
+        self.canvas.present();
+        self.last_present = Instant::now();
+
+        Ok(())
+    }
+
+    /// Switches to the next filter and shows its name in the title.
+    pub fn next_filter(&mut self) -> Result<()> {
+        self.filter = self.filter.next();
+        // force the new filter to be applied
+        self.uploaded = None;
+
+        let name = self.filter.to_possible_value().unwrap();
+        self.canvas
+            .window_mut()
+            .set_title(&format!("CHIRP-8 [{}]", name.get_name()))
+            .map_err(|_| eyre!("failed to set the window title"))
+    }
+
+    /// Saves the latest framebuffer, see [`screenshot::save`] for the formats.
+    pub fn screenshot(&self, path: &Path) -> Result<()> {
+        screenshot::save(path, &self.current, &self.renderer.palette, self.scale())
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.renderer.palette
+    }
+
+    /// Size of a CHIP-8 pixel on screen, rounded down
+    pub fn scale(&self) -> u32 {
+        (self.viewport().width() / self.resolution().0).max(1)
+    }
 
-        self.update()
+    /// Takes the held keys to highlight on the keypad.
+    pub fn set_keys(&mut self, keys: &[bool; 16]) {
+        self.keys = *keys;
     }
 
-    pub fn update(&mut self) -> Result<()> {
-        for i in 0..Self::WIDTH {
-            for j in 0..Self::HEIGHT {
-                let rect = Rect::new(
-                    i as i32 * self.scale as i32,
-                    j as i32 * self.scale as i32,
-                    self.scale,
-                    self.scale,
-                );
+    /// The keypad key at a position in window coordinates, as in mouse events
+    pub fn key_at_point(&self, x: i32, y: i32) -> Option<u8> {
+        let (width, height) = self.canvas.window().size();
 
-                if self.buffer[i as usize][j as usize] {
-                    self.canvas.set_draw_color(Color::RGB(255, 255, 255));
-                } else {
-                    self.canvas.set_draw_color(Color::RGB(0, 0, 0));
-                }
+        self.key_at_touch(x as f32 / width as f32, y as f32 / height as f32)
+    }
+
+    /// The keypad key at a position relative to the window size (0.0 - 1.0), as in touch
+    /// events
+    pub fn key_at_touch(&self, x: f32, y: f32) -> Option<u8> {
+        let output = self.output_size();
+        let (_, area) = self.keypad?.split(output);
+
+        keypad::key_at(
+            area,
+            (x * output.0 as f32) as i32,
+            (y * output.1 as f32) as i32,
+        )
+    }
+
+    /// Must be called when the window size changed, the filter output depends on it.
+    pub fn resized(&mut self) {
+        self.uploaded = None;
+    }
 
-                self.canvas
-                    .fill_rect(rect)
-                    .map_err(|_| eyre!("failed to draw"))?;
+    /// Switches between a borderless fullscreen window and the previous window geometry.
+    pub fn toggle_fullscreen(&mut self) -> Result<()> {
+        let fullscreen = match self.canvas.window().fullscreen_state() {
+            FullscreenType::Off => {
+                self.windowed = Some(self.window_geometry());
+                FullscreenType::Desktop
             }
+            _ => FullscreenType::Off,
+        };
+
+        self.canvas
+            .window_mut()
+            .set_fullscreen(fullscreen)
+            .map_err(|_| eyre!("failed to toggle fullscreen"))?;
+
+        if let (FullscreenType::Off, Some(g)) = (fullscreen, self.windowed.take()) {
+            let window = self.canvas.window_mut();
+            window
+                .set_size(g.width, g.height)
+                .map_err(|_| eyre!("failed to resize the window"))?;
+            window.set_position(WindowPos::Positioned(g.x), WindowPos::Positioned(g.y));
         }
 
-        self.canvas.present();
+        self.resized();
 
         Ok(())
     }
+
+    /// Remembers the window geometry for the next run. Failing to do so isn't worth stopping
+    /// for, so it only prints a warning.
+    pub fn save_geometry(&self) {
+        let geometry = self.windowed.unwrap_or_else(|| self.window_geometry());
+
+        if let Err(err) = geometry.save() {
+            eprintln!("warning: failed to save the window geometry: {err:#}");
+        }
+    }
+
+    fn window_geometry(&self) -> Geometry {
+        let window = self.canvas.window();
+        let (x, y) = window.position();
+        let (width, height) = window.size();
+
+        Geometry {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Size of the framebuffer in CHIP-8 pixels
+    fn resolution(&self) -> (u32, u32) {
+        (self.current.len() as u32, self.current[0].len() as u32)
+    }
+
+    /// Size of the window in pixels, which can differ from its size in window coordinates
+    fn output_size(&self) -> (u32, u32) {
+        self.canvas
+            .output_size()
+            .unwrap_or_else(|_| self.canvas.window().size())
+    }
+
+    /// Where the image is drawn in the window
+    fn viewport(&self) -> Rect {
+        let output = self.output_size();
+        let game = match self.keypad {
+            Some(placement) => placement.split(output).0,
+            None => Rect::new(0, 0, output.0, output.1),
+        };
+
+        let mut viewport = letterbox(game.size(), self.resolution(), self.integer_scaling);
+        viewport.offset(game.x(), game.y());
+
+        viewport
+    }
+
+    /// Filters the image and uploads it, recreating the texture if the size changed.
+    fn upload(&mut self, image: &Image) -> Result<()> {
+        // filters draw their effects at the size a CHIP-8 pixel has on screen
+        let filtered = self.filter.apply(image, self.scale() as usize);
+
+        let query = self.texture.query();
+        if (query.width as usize, query.height as usize) != (filtered.width, filtered.height) {
+            self.texture = self
+                .canvas
+                .texture_creator()
+                .create_texture_streaming(
+                    PixelFormatEnum::RGB24,
+                    filtered.width as u32,
+                    filtered.height as u32,
+                )
+                .map_err(|_| eyre!("failed to create a texture"))?;
+        }
+
+        self.texture
+            .update(None, &filtered.pixels, filtered.pitch())
+            .map_err(|_| eyre!("failed to update the texture"))
+    }
+}
+
+/// Turns framebuffers into RGB24 pixels, one call per display refresh.
+struct Renderer {
+    palette: Palette,
+    persistence: Persistence,
+    /// How much brightness a pixel that is off loses every refresh
+    decay_step: f32,
+    /// Framebuffer shown on the previous refresh
+    previous: Framebuffer,
+    /// Brightness of every pixel, from 0.0 (background) to 1.0 (foreground)
+    brightness: [[f32; HEIGHT as usize]; WIDTH as usize],
+}
+
+impl Renderer {
+    fn new(palette: Palette, persistence: Persistence, decay_frames: u32) -> Self {
+        Self {
+            palette,
+            persistence,
+            decay_step: 1.0 / decay_frames.max(1) as f32,
+            previous: [[false; HEIGHT as usize]; WIDTH as usize],
+            brightness: [[0.0; HEIGHT as usize]; WIDTH as usize],
+        }
+    }
+
+    fn render(&mut self, current: &Framebuffer) -> Image {
+        let mut image = Image::new(WIDTH as usize, HEIGHT as usize);
+
+        for (x, column) in current.iter().enumerate() {
+            for (y, &on) in column.iter().enumerate() {
+                let brightness = &mut self.brightness[x][y];
+
+                *brightness = match self.persistence {
+                    Persistence::None => on as u8 as f32,
+                    Persistence::Decay if on => 1.0,
+                    Persistence::Decay => (*brightness - self.decay_step).max(0.0),
+                    Persistence::Blend => (on as u8 + self.previous[x][y] as u8) as f32 / 2.0,
+                };
+
+                let color = mix(self.palette.background(), self.palette.0[1], *brightness);
+
+                let offset = y * PITCH + x * BPP;
+                image.pixels[offset..offset + BPP].copy_from_slice(&[color.r, color.g, color.b]);
+            }
+        }
+
+        self.previous = *current;
+
+        image
+    }
+}
+
+/// Largest rectangle with the aspect ratio of `resolution` centered in `output`. With
+/// `integer` the scale is rounded down to a whole number, unless it would be zero.
+fn letterbox(output: (u32, u32), resolution: (u32, u32), integer: bool) -> Rect {
+    let scale = f64::min(
+        output.0 as f64 / resolution.0 as f64,
+        output.1 as f64 / resolution.1 as f64,
+    );
+    let scale = if integer && scale >= 1.0 {
+        scale.floor()
+    } else {
+        scale
+    };
+
+    let width = ((resolution.0 as f64 * scale) as u32).max(1);
+    let height = ((resolution.1 as f64 * scale) as u32).max(1);
+
+    Rect::new(
+        ((output.0.saturating_sub(width)) / 2) as i32,
+        ((output.1.saturating_sub(height)) / 2) as i32,
+        width,
+        height,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::Color;
+
+    fn pixel(image: &Image, x: usize, y: usize) -> Color {
+        let offset = y * PITCH + x * BPP;
+
+        Color::rgb(
+            image.pixels[offset],
+            image.pixels[offset + 1],
+            image.pixels[offset + 2],
+        )
+    }
+
+    #[test]
+    fn phosphor_decay() {
+        let mut renderer = Renderer::new(Palette::default(), Persistence::Decay, 4);
+        let mut buffer = [[false; HEIGHT as usize]; WIDTH as usize];
+
+        buffer[1][2] = true;
+        assert_eq!(
+            pixel(&renderer.render(&buffer), 1, 2),
+            Color::rgb(0xFF, 0xFF, 0xFF)
+        );
+
+        buffer[1][2] = false;
+        let levels = (0..4)
+            .map(|_| pixel(&renderer.render(&buffer), 1, 2).r)
+            .collect::<Vec<_>>();
+
+        assert_eq!(levels, [191, 128, 64, 0]);
+    }
+
+    #[test]
+    fn frame_blending() {
+        let mut renderer = Renderer::new(Palette::default(), Persistence::Blend, 4);
+        let mut buffer = [[false; HEIGHT as usize]; WIDTH as usize];
+
+        buffer[0][0] = true;
+        assert_eq!(pixel(&renderer.render(&buffer), 0, 0).r, 128);
+        assert_eq!(
+            pixel(&renderer.render(&buffer), 0, 0),
+            Color::rgb(0xFF, 0xFF, 0xFF)
+        );
+
+        buffer[0][0] = false;
+        assert_eq!(pixel(&renderer.render(&buffer), 0, 0).r, 128);
+        assert_eq!(pixel(&renderer.render(&buffer), 0, 0), Color::rgb(0, 0, 0));
+    }
+
+    #[test]
+    fn letterboxing() {
+        // wider than 2:1, bars on the sides
+        assert_eq!(
+            letterbox((1000, 320), (64, 32), false),
+            Rect::new(180, 0, 640, 320)
+        );
+        // taller than 2:1, bars on the top and bottom
+        assert_eq!(
+            letterbox((640, 640), (64, 32), false),
+            Rect::new(0, 160, 640, 320)
+        );
+        // 9.375x rounded down to 9x
+        assert_eq!(
+            letterbox((600, 300), (64, 32), true),
+            Rect::new(12, 6, 576, 288)
+        );
+        // integer scaling never makes the image disappear
+        assert_eq!(letterbox((32, 16), (64, 32), true), Rect::new(0, 0, 32, 16));
+    }
+
+    #[test]
+    fn no_persistence() {
+        let mut renderer = Renderer::new(Palette::default(), Persistence::None, 4);
+        let mut buffer = [[false; HEIGHT as usize]; WIDTH as usize];
+
+        buffer[3][4] = true;
+        assert_eq!(
+            pixel(&renderer.render(&buffer), 3, 4),
+            Color::rgb(0xFF, 0xFF, 0xFF)
+        );
+
+        buffer[3][4] = false;
+        assert_eq!(pixel(&renderer.render(&buffer), 3, 4), Color::rgb(0, 0, 0));
+    }
 }
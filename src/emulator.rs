@@ -1,319 +1,382 @@
-use color_eyre::{
-    eyre::{bail, eyre, Context as _},
-    Result,
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
-use rand::Rng as _;
-use rodio::Sink;
-use sdl2::{keyboard::Scancode, EventPump, Sdl};
+
+use color_eyre::{eyre::eyre, Result};
+use sdl2::{
+    keyboard::{Keycode, Mod, Scancode},
+    mouse::MouseButton,
+    EventPump, Sdl,
+};
+
+use chirp_8::machine::Machine;
 
 use crate::{
+    debug_view::DebugView,
+    debugger::Debugger,
     display::Display,
-    instruction::{Instruction, IntExt as _},
-    memory::{Memory, RegIdx, Registers},
-    sound::SawWave,
+    gamepad::{GamepadMap, Gamepads},
+    gdb::GdbStub,
+    keymap::Keymap,
+    recorder::{Recorder, FPS},
+    sound::Wav,
+    speaker::Speaker,
     Args,
 };
 
 pub struct Chip8 {
     args: Args,
 
-    memory: Memory,
+    machine: Machine,
     display: Display,
-    stack: Vec<u16>,
     event_pump: EventPump,
-
-    regs: Registers,
-    index: u16,
-
-    delay_timer: u8,
-    sound_timer: u8,
-
-    pc: u16,
+    keymap: Keymap,
+    /// Only with `--debug` or `--debug-view`
+    debugger: Option<Debugger>,
+    debug_view: Option<DebugView>,
+    /// Only with `--gdb`
+    gdb: Option<GdbStub>,
+    /// `None` if game controllers aren't available
+    gamepads: Option<Gamepads>,
+    speaker: Speaker,
+    recorder: Option<Recorder>,
+    /// Emulated time the recording started at, see [`Machine::cycles`]
+    recording_start: u64,
+    /// Sound of the recording, with `--record-audio`
+    wav: Option<Wav>,
+    /// Keypad key pressed by each held keyboard key
+    held_keys: HashMap<Scancode, u8>,
+    /// Keypad key held down with the mouse
+    clicked: Option<u8>,
+    /// Keypad keys held down by each finger
+    touches: HashMap<i64, u8>,
 }
 
+/// SDL repeats touches as mouse events from this mouse, they are handled as touches already
+const TOUCH_MOUSE_ID: u32 = u32::MAX;
+
 impl Chip8 {
     pub fn new(rom: &[u8], context: Sdl, args: Args) -> Result<Self> {
-        let scale = args.scale;
-
-        Ok(Self {
-            args,
-
-            memory: Memory::new(rom),
-            display: Display::new(&context, scale)?,
+        let machine = Machine::new(rom, args.quirks());
+
+        let mut emulator = Self {
+            debugger: (args.debug || args.debug_view).then(|| Debugger::new(&machine, args.debug)),
+            debug_view: match args.debug_view {
+                true => Some(DebugView::new(&context, args.ips)?),
+                false => None,
+            },
+            machine,
+            display: Display::new(&context, &args, args.palette()?)?,
+            // GDB connects while the main loop runs, so the window keeps responding meanwhile
+            gdb: args.gdb.map(GdbStub::listen).transpose()?,
             event_pump: context
                 .event_pump()
                 .map_err(|_| eyre!("failed to initialize event pump"))?,
-            stack: Vec::new(),
+            keymap: args.keymap()?,
+            gamepads: Gamepads::new(
+                &context,
+                GamepadMap::load(args.gamepad.as_deref(), &args.rom)?,
+            )
+            .inspect_err(|err| eprintln!("warning: {err}, gamepads are disabled"))
+            .ok(),
+            speaker: Speaker::open(args.audio, &context, args.tone(), args.ips),
+            recorder: None,
+            recording_start: 0,
+            wav: None,
+            held_keys: HashMap::new(),
+            clicked: None,
+            touches: HashMap::new(),
 
-            regs: Registers::new(),
-            index: 0,
+            args,
+        };
 
-            sound_timer: 0,
-            delay_timer: 0,
+        if let Some(path) = emulator.args.record.clone() {
+            emulator.start_recording(&path)?;
+        }
 
-            pc: 512,
-        })
+        Ok(emulator)
     }
 
     pub fn run(mut self) -> Result<()> {
-        self.display.update()?;
+        self.display.update(&self.machine.framebuffer)?;
 
         let mut now = std::time::Instant::now();
-        let mut keys = [false; 16];
-        let mut rng = rand::rng();
-        let (_stream, stream_handle) =
-            rodio::OutputStream::try_default().wrap_err("failed to initialize sound")?;
-        let sink = Sink::try_new(&stream_handle)?;
 
-        sink.pause();
+        loop {
+            let events = self.event_pump.poll_iter().collect::<Vec<_>>();
+            for event in events {
+                use sdl2::event::{Event, WindowEvent};
 
-        sink.set_volume(self.args.volume.min(100) as f32 / 100.0 * 0.025);
-        sink.append(SawWave::new(440.0, 48000));
+                if let Some(gamepads) = &mut self.gamepads {
+                    gamepads.handle(&event, &mut self.machine);
+                }
 
-        loop {
-            for event in self.event_pump.poll_iter() {
-                use sdl2::event::Event;
+                if let (Some(view), Some(debugger)) = (&mut self.debug_view, &mut self.debugger) {
+                    if view.handle(&event, debugger, &mut self.machine) {
+                        self.debug_view = None;
+                        // nothing else could continue the machine
+                        if !self.args.debug {
+                            self.debugger = None;
+                        }
+                    }
+                }
 
                 match event {
-                    Event::Quit { .. } | Event::AppTerminating { .. } => return Ok(()),
+                    Event::Quit { .. } | Event::AppTerminating { .. } => {
+                        return self.quit();
+                    }
+                    // with the debugger window open, closing the game doesn't quit by itself
+                    Event::Window {
+                        window_id,
+                        win_event: WindowEvent::Close,
+                        ..
+                    } if window_id == self.display.window_id() => {
+                        return self.quit();
+                    }
+                    Event::Window {
+                        win_event: WindowEvent::SizeChanged(..),
+                        ..
+                    } => self.display.resized(),
                     Event::KeyDown {
-                        scancode: Some(scancode),
+                        scancode: Some(Scancode::F11),
+                        repeat: false,
                         ..
-                    } => {
-                        if let Some(idx) = scancode_to_key(scancode) {
-                            keys[idx as usize] = true
+                    } => self.display.toggle_fullscreen()?,
+                    Event::KeyDown {
+                        scancode: Some(Scancode::Return),
+                        keymod,
+                        repeat: false,
+                        ..
+                    } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                        self.display.toggle_fullscreen()?
+                    }
+                    Event::KeyDown {
+                        scancode: Some(Scancode::F2),
+                        repeat: false,
+                        ..
+                    } => self.display.next_filter()?,
+                    Event::KeyDown {
+                        scancode: Some(Scancode::F12),
+                        repeat: false,
+                        ..
+                    } => self.screenshot()?,
+                    Event::KeyDown {
+                        scancode: Some(Scancode::F9),
+                        repeat: false,
+                        ..
+                    } => match self.recorder {
+                        Some(_) => self.stop_recording()?,
+                        None => self.start_recording(&PathBuf::from(format!(
+                            "chirp-8-{}.gif",
+                            timestamp()
+                        )))?,
+                    },
+                    Event::MouseButtonDown {
+                        which,
+                        mouse_btn: MouseButton::Left,
+                        x,
+                        y,
+                        ..
+                    } if which != TOUCH_MOUSE_ID => {
+                        self.clicked = self.display.key_at_point(x, y);
+                        if let Some(key) = self.clicked {
+                            self.machine.set_key(key, true);
                         }
                     }
-                    Event::KeyUp {
-                        scancode: Some(scancode),
+                    Event::MouseButtonUp {
+                        which,
+                        mouse_btn: MouseButton::Left,
                         ..
+                    } if which != TOUCH_MOUSE_ID => {
+                        if let Some(key) = self.clicked.take() {
+                            self.machine.set_key(key, false);
+                        }
+                    }
+                    Event::FingerDown {
+                        finger_id, x, y, ..
                     } => {
-                        if let Some(idx) = scancode_to_key(scancode) {
-                            keys[idx as usize] = false
+                        if let Some(key) = self.display.key_at_touch(x, y) {
+                            self.touches.insert(finger_id, key);
+                            self.machine.set_key(key, true);
                         }
                     }
+                    Event::FingerUp { finger_id, .. } => {
+                        if let Some(key) = self.touches.remove(&finger_id) {
+                            self.machine.set_key(key, false);
+                        }
+                    }
+                    Event::KeyDown {
+                        scancode: Some(scancode),
+                        keycode,
+                        ..
+                    } => self.keyboard_key(scancode, keycode, true),
+                    Event::KeyUp {
+                        scancode: Some(scancode),
+                        keycode,
+                        ..
+                    } => self.keyboard_key(scancode, keycode, false),
                     _ => {}
                 }
             }
 
-            match self.fetch_and_decode()? {
-                Instruction::ClearScreen => self.display.clear()?,
-                Instruction::Jump(nnn) => self.pc = nnn,
-                Instruction::SetIm(vx, nn) => self.regs[vx] = nn,
-                Instruction::AddIm(vx, nn) => self.regs[vx] = self.regs[vx].wrapping_add(nn),
-                Instruction::SetIndex(nnn) => self.index = nnn,
-                Instruction::Draw(vx, vy, n) => self.draw(vx, vy, n)?,
-                Instruction::Call(addr) => {
-                    self.stack.push(self.pc);
-                    self.pc = addr;
-                }
-                Instruction::Return => match self.stack.pop() {
-                    Some(pc) => self.pc = pc,
-                    None => bail!("invalid return; call stack is empty"),
-                },
-                Instruction::SkipEqIm(vx, nn) => self.skip_if(|s| s.regs[vx] == nn),
-                Instruction::SkipNeIm(vx, nn) => self.skip_if(|s| s.regs[vx] != nn),
-                Instruction::SkipEq(vx, vy) => {
-                    self.skip_if(|s| s.regs[vx] == s.regs[vy]);
-                }
-                Instruction::SkipNe(vx, vy) => {
-                    self.skip_if(|s| s.regs[vx] != s.regs[vy]);
-                }
-                Instruction::Set(vx, vy) => self.regs[vx] = self.regs[vy],
-                Instruction::Or(vx, vy) => self.regs[vx] |= self.regs[vy],
-                Instruction::And(vx, vy) => self.regs[vx] &= self.regs[vy],
-                Instruction::Xor(vx, vy) => self.regs[vx] ^= self.regs[vy],
-                Instruction::Add(vx, vy) => {
-                    let (result, overflow) = self.regs[vx].overflowing_add(self.regs[vy]);
-
-                    (self.regs[vx], self.regs[RegIdx::FLAG]) = (result, overflow as u8);
-                }
-                Instruction::Sub(vx, vy) => {
-                    let (x, y) = (self.regs[vx], self.regs[vy]);
-
-                    self.regs[vx] = x.wrapping_sub(y);
-                    self.regs[RegIdx::FLAG] = (x >= y) as u8;
-                }
-                Instruction::SubOpp(vx, vy) => {
-                    let (x, y) = (self.regs[vx], self.regs[vy]);
-
-                    self.regs[vx] = y.wrapping_sub(x);
-                    self.regs[RegIdx::FLAG] = (y >= x) as u8;
-                }
-                Instruction::Shr(vx, vy) => {
-                    if self.args.old_shift_behavior {
-                        self.regs[vx] = self.regs[vy];
-                    }
-
-                    self.regs[RegIdx::FLAG] = self.regs[vx] & 1;
-                    self.regs[vx] >>= 1;
-                }
-                Instruction::Shl(vx, vy) => {
-                    if self.args.old_shift_behavior {
-                        self.regs[vx] = self.regs[vy];
-                    }
+            let run = match (&mut self.debugger, &mut self.gdb) {
+                (Some(debugger), _) => debugger.poll(&mut self.machine),
+                (None, Some(gdb)) => gdb.poll(&mut self.machine),
+                (None, None) => true,
+            };
+            if self.gdb.as_ref().is_some_and(GdbStub::killed) {
+                return self.quit();
+            }
 
-                    self.regs[RegIdx::FLAG] = (self.regs[vx] & 0b10000000) >> 7;
-                    self.regs[vx] <<= 1;
-                }
-                Instruction::JumpV0(nnn) => {
-                    let reg = RegIdx::new(if self.args.new_jump_behavior {
-                        nnn.nibble2()
-                    } else {
-                        0
-                    });
-
-                    self.pc = self.regs[reg] as u16 + nnn;
+            if run {
+                let pc = self.machine.pc;
+                let result = self.machine.step();
+                if let Some(debugger) = &mut self.debugger {
+                    debugger.after_step(&mut self.machine, pc);
                 }
-                Instruction::RandAnd(vx, nn) => self.regs[vx] = rng.random::<u8>() & nn,
-                Instruction::GetDelay(vx) => self.regs[vx] = self.delay_timer,
-                Instruction::SetDelay(vx) => self.delay_timer = self.regs[vx],
-                Instruction::SetSound(vx) => self.sound_timer = self.regs[vx],
-                Instruction::AddIndex(vx) => {
-                    self.index += self.regs[vx] as u16;
-                    if self.args.index_overflow && self.index >= 4096 {
-                        self.regs[RegIdx::FLAG] = 1;
-                    }
+                let result = match &mut self.gdb {
+                    Some(gdb) => gdb.after_step(&mut self.machine, pc, result),
+                    None => result,
+                };
+
+                if let Err(err) = result {
+                    let Some(debugger) = &mut self.debugger else {
+                        return Err(err);
+                    };
+
+                    // leave pc at the instruction that failed
+                    self.machine.pc = pc;
+                    debugger.error(&self.machine, err)?;
                 }
-                Instruction::SetBcd(vx) => {
-                    let x = self.regs[vx];
 
-                    self.memory.write_u8(self.index, x / 100)?;
-                    self.memory.write_u8(self.index + 1, (x / 10) % 10)?;
-                    self.memory.write_u8(self.index + 2, x % 10)?;
+                if let Some(view) = &mut self.debug_view {
+                    view.record(&mut self.machine);
                 }
-                Instruction::RegStore(vx) => {
-                    for idx in 0..=vx.as_u8() {
-                        self.memory
-                            .write_u8(self.index + idx as u16, self.regs[RegIdx::new(idx)])?;
-                    }
 
-                    if self.args.old_store_load_behavior {
-                        self.index += vx.as_u8() as u16 + 1;
+                // a frame every 1/60 s of emulated time, like headless runs and the WAV
+                if let Some(recorder) = &mut self.recorder {
+                    let elapsed = self.machine.cycles - self.recording_start;
+                    while recorder.frames() < elapsed * FPS as u64 / self.args.ips {
+                        recorder.frame(&self.machine.framebuffer)?;
                     }
                 }
-                Instruction::RegLoad(vx) => {
-                    for i in 0..=vx.as_u8() {
-                        self.regs[RegIdx::new(i)] = self.memory.read_u8(self.index + i as u16)?;
-                    }
+            }
 
-                    if self.args.old_store_load_behavior {
-                        self.index += vx.as_u8() as u16 + 1;
-                    }
-                }
-                Instruction::IndexCharacter(vx) => {
-                    let x = self.regs[vx];
+            if let (Some(view), Some(debugger)) = (&mut self.debug_view, &self.debugger) {
+                view.draw(&self.machine, debugger)?;
+            }
 
-                    self.index = 0x50 + 5 * (x as u16 & 0x0F);
-                }
-                Instruction::GetKey(vx) => {
-                    if let Some(key) = keys.iter().position(|&x| x) {
-                        self.regs[vx] = key as u8;
-                    } else {
-                        self.pc -= 2; // do this instruction again
-                    }
-                }
-                Instruction::SkipKeyEq(vx) => {
-                    if keys[self.regs[vx] as usize] {
-                        self.pc += 2;
-                    }
-                }
-                Instruction::SkipKeyNe(vx) => {
-                    if !keys[self.regs[vx] as usize] {
-                        self.pc += 2;
-                    }
-                }
+            if self.machine.take_redraw() {
+                self.display.update(&self.machine.framebuffer)?;
             }
+            self.display.set_keys(self.machine.keys());
+            self.display.present()?;
 
             let millis = now.elapsed().as_millis();
-            if millis > self.args.timer_period {
+            if !run {
+                // time stands still while paused
+                now = std::time::Instant::now();
+            } else if millis > self.args.timer_period {
                 let decrement = (millis / self.args.timer_period) as u8;
-
-                self.delay_timer = self.delay_timer.saturating_sub(decrement);
-                self.sound_timer = self.sound_timer.saturating_sub(decrement);
+                self.machine.tick_timers(decrement);
 
                 now = std::time::Instant::now();
-
-                // HACK:? if it's not here, if nothing is being drawn and you switch to another
-                // window, the screen is being weird
-                self.display.update()?;
             }
 
-            if self.sound_timer > 0 {
-                sink.play();
-            } else {
-                sink.pause();
+            let events = self.machine.take_sound_events();
+            if let Some(wav) = &mut self.wav {
+                wav.push(&events);
+                wav.write_until(self.machine.cycles)?;
             }
+            self.speaker.send(events);
 
             std::thread::sleep(std::time::Duration::from_secs_f64(
                 1f64 / self.args.ips as f64,
             ));
         }
     }
+}
+
+impl Chip8 {
+    /// Saves the screen as a PNG and a PBM named after the current time.
+    fn screenshot(&self) -> Result<()> {
+        let stamp = timestamp();
+
+        for ext in ["png", "pbm"] {
+            let path = PathBuf::from(format!("chirp-8-{stamp}.{ext}"));
+            self.display.screenshot(&path)?;
+            println!("saved {}", path.display());
+        }
 
-    fn fetch_and_decode(&mut self) -> Result<Instruction> {
-        let inst = self.memory.read_u16(self.pc)?;
-        self.pc += 2;
+        Ok(())
+    }
 
-        Instruction::decode(inst).ok_or_else(|| eyre!("unknown instruction: {inst:04x}"))
+    /// Remembers the window and finishes the recording before the emulator exits.
+    fn quit(&mut self) -> Result<()> {
+        self.display.save_geometry();
+        self.stop_recording()
     }
 
-    fn skip_if(&mut self, pred: impl Fn(&Self) -> bool) {
-        if pred(self) {
-            self.pc += 2
+    /// Several keys can press the same keypad key, it's released with the last of them.
+    fn keyboard_key(&mut self, scancode: Scancode, keycode: Option<Keycode>, pressed: bool) {
+        let Some(idx) = self.keymap.get_sdl(scancode, keycode) else {
+            return;
+        };
+
+        if pressed {
+            self.held_keys.insert(scancode, idx);
+            self.machine.set_key(idx, true);
+        } else if self.held_keys.remove(&scancode).is_some()
+            && !self.held_keys.values().any(|&held| held == idx)
+        {
+            self.machine.set_key(idx, false);
         }
     }
 
-    fn draw(&mut self, vx: RegIdx, vy: RegIdx, n: u8) -> Result<()> {
-        let (x, y) = (
-            self.regs[vx] % Display::WIDTH,
-            self.regs[vy] % Display::HEIGHT,
-        );
-        self.regs[RegIdx::FLAG] = 0;
-
-        for i in 0..n {
-            let pixel = self.memory.read_u8(self.index + i as u16)?;
-            for j in 0..8 {
-                if (pixel & (0x80 >> j)) != 0 {
-                    if x + j >= Display::WIDTH || y + i >= Display::HEIGHT {
-                        continue;
-                    }
+    /// Records at the current palette and scale, which stay fixed until recording stops.
+    fn start_recording(&mut self, path: &Path) -> Result<()> {
+        self.recorder = Some(Recorder::start(
+            path,
+            self.display.palette(),
+            self.display.scale(),
+        )?);
+        self.recording_start = self.machine.cycles;
+        println!("recording to {}", path.display());
+
+        if self.args.record_audio {
+            let path = path.with_extension("wav");
+            self.wav = Some(Wav::create(
+                &path,
+                self.args.tone(),
+                self.args.ips,
+                self.machine.cycles,
+            )?);
+            println!("recording sound to {}", path.display());
+        }
 
-                    if self.display.buffer[(x + j) as usize][(y + i) as usize] {
-                        self.regs[RegIdx::FLAG] = 1
-                    }
+        Ok(())
+    }
 
-                    self.display.buffer[(x + j) as usize][(y + i) as usize] ^= true;
-                }
-            }
+    fn stop_recording(&mut self) -> Result<()> {
+        if let Some(recorder) = self.recorder.take() {
+            let frames = recorder.finish()?;
+            println!("recorded {frames} frames");
         }
 
-        self.display.update()
+        if let Some(wav) = self.wav.take() {
+            wav.finish()?;
+        }
+
+        Ok(())
     }
 }
 
-const fn scancode_to_key(scancode: Scancode) -> Option<u8> {
-    match scancode {
-        Scancode::Num1 => Some(0),
-        Scancode::Num2 => Some(1),
-        Scancode::Num3 => Some(2),
-        Scancode::Num4 => Some(3),
-
-        Scancode::Q => Some(4),
-        Scancode::W => Some(5),
-        Scancode::E => Some(6),
-        Scancode::R => Some(7),
-
-        Scancode::A => Some(8),
-        Scancode::S => Some(9),
-        Scancode::D => Some(10),
-        Scancode::F => Some(11),
-
-        Scancode::Z => Some(12),
-        Scancode::X => Some(13),
-        Scancode::C => Some(14),
-        Scancode::V => Some(15),
-
-        _ => None,
-    }
+/// Milliseconds since the Unix epoch, used to name screenshots and recordings
+fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
 }
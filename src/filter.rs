@@ -0,0 +1,356 @@
+// without the window nothing is filtered, screenshots and recordings only use [`Image`]
+#![cfg_attr(not(feature = "window"), allow(dead_code))]
+
+use clap::ValueEnum;
+
+/// Bytes per pixel of RGB24 images
+const BPP: usize = 3;
+
+/// Post-processing applied on the CPU before the image is uploaded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Filter {
+    /// Plain nearest neighbor scaling
+    #[default]
+    None,
+    /// Scale2x (EPX) edge smoothing
+    Scale2x,
+    /// Scale3x edge smoothing
+    Scale3x,
+    /// Darkened gaps between rows of pixels
+    Scanlines,
+    /// Gaps between all pixels, like an LCD
+    Grid,
+    /// Curved screen with scanlines and darkened corners
+    Crt,
+}
+
+impl Filter {
+    const ALL: [Filter; 6] = [
+        Filter::None,
+        Filter::Scale2x,
+        Filter::Scale3x,
+        Filter::Scanlines,
+        Filter::Grid,
+        Filter::Crt,
+    ];
+
+    /// The filter after this one, wrapping around
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&f| f == self).unwrap();
+
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+
+    /// Applies the filter, `scale` is the size of a CHIP-8 pixel on screen. Filters that only
+    /// smooth edges ignore it and produce a fixed size image.
+    pub fn apply(self, image: &Image, scale: usize) -> Image {
+        let scale = scale.max(1);
+
+        match self {
+            Filter::None => image.clone(),
+            Filter::Scale2x => scale2x(image),
+            Filter::Scale3x => scale3x(image),
+            Filter::Scanlines => {
+                let mut out = image.upscale(scale);
+
+                for y in (0..out.height).filter(|y| is_gap(*y, scale)) {
+                    for x in 0..out.width {
+                        out.darken(x, y, 0.5);
+                    }
+                }
+
+                out
+            }
+            Filter::Grid => {
+                let mut out = image.upscale(scale);
+
+                for y in 0..out.height {
+                    for x in 0..out.width {
+                        if is_gap(x, scale) || is_gap(y, scale) {
+                            out.darken(x, y, 0.25);
+                        }
+                    }
+                }
+
+                out
+            }
+            Filter::Crt => crt(image, scale),
+        }
+    }
+}
+
+/// Whether the row or column is the last one of a scaled up pixel, only if there's room for it
+fn is_gap(pos: usize, scale: usize) -> bool {
+    scale >= 3 && pos % scale == scale - 1
+}
+
+/// An RGB24 image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * BPP],
+        }
+    }
+
+    /// Bytes per row
+    pub fn pitch(&self) -> usize {
+        self.width * BPP
+    }
+
+    fn get(&self, x: usize, y: usize) -> [u8; BPP] {
+        let offset = (y * self.width + x) * BPP;
+
+        [
+            self.pixels[offset],
+            self.pixels[offset + 1],
+            self.pixels[offset + 2],
+        ]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: [u8; BPP]) {
+        let offset = (y * self.width + x) * BPP;
+
+        self.pixels[offset..offset + BPP].copy_from_slice(&color);
+    }
+
+    /// Multiplies the pixel's color by `factor`
+    fn darken(&mut self, x: usize, y: usize, factor: f32) {
+        let color = self.get(x, y).map(|c| (c as f32 * factor) as u8);
+
+        self.set(x, y, color);
+    }
+
+    pub fn upscale(&self, scale: usize) -> Self {
+        let mut out = Self::new(self.width * scale, self.height * scale);
+
+        for y in 0..out.height {
+            for x in 0..out.width {
+                out.set(x, y, self.get(x / scale, y / scale));
+            }
+        }
+
+        out
+    }
+
+    /// Neighbors of a pixel as (up, left, right, down), edges are repeated
+    fn neighbors(&self, x: usize, y: usize) -> [[u8; BPP]; 4] {
+        [
+            self.get(x, y.saturating_sub(1)),
+            self.get(x.saturating_sub(1), y),
+            self.get((x + 1).min(self.width - 1), y),
+            self.get(x, (y + 1).min(self.height - 1)),
+        ]
+    }
+}
+
+fn scale2x(image: &Image) -> Image {
+    let mut out = Image::new(image.width * 2, image.height * 2);
+
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let p = image.get(x, y);
+            let [a, c, b, d] = image.neighbors(x, y);
+
+            let (mut e0, mut e1, mut e2, mut e3) = (p, p, p, p);
+            if c == a && c != d && a != b {
+                e0 = a;
+            }
+            if a == b && a != c && b != d {
+                e1 = b;
+            }
+            if d == c && d != b && c != a {
+                e2 = c;
+            }
+            if b == d && b != a && d != c {
+                e3 = d;
+            }
+
+            out.set(2 * x, 2 * y, e0);
+            out.set(2 * x + 1, 2 * y, e1);
+            out.set(2 * x, 2 * y + 1, e2);
+            out.set(2 * x + 1, 2 * y + 1, e3);
+        }
+    }
+
+    out
+}
+
+fn scale3x(image: &Image) -> Image {
+    let mut out = Image::new(image.width * 3, image.height * 3);
+    let at = |x: isize, y: isize| {
+        image.get(
+            x.clamp(0, image.width as isize - 1) as usize,
+            y.clamp(0, image.height as isize - 1) as usize,
+        )
+    };
+
+    for y in 0..image.height as isize {
+        for x in 0..image.width as isize {
+            // a b c
+            // d e f
+            // g h i
+            let (a, b, c) = (at(x - 1, y - 1), at(x, y - 1), at(x + 1, y - 1));
+            let (d, e, f) = (at(x - 1, y), at(x, y), at(x + 1, y));
+            let (g, h, i) = (at(x - 1, y + 1), at(x, y + 1), at(x + 1, y + 1));
+
+            let mut block = [e; 9];
+            if b != h && d != f {
+                block[0] = if d == b { d } else { e };
+                block[1] = if (d == b && e != c) || (b == f && e != a) {
+                    b
+                } else {
+                    e
+                };
+                block[2] = if b == f { f } else { e };
+                block[3] = if (d == b && e != g) || (d == h && e != a) {
+                    d
+                } else {
+                    e
+                };
+                block[5] = if (b == f && e != i) || (h == f && e != c) {
+                    f
+                } else {
+                    e
+                };
+                block[6] = if d == h { d } else { e };
+                block[7] = if (d == h && e != i) || (h == f && e != g) {
+                    h
+                } else {
+                    e
+                };
+                block[8] = if h == f { f } else { e };
+            }
+
+            for (idx, color) in block.into_iter().enumerate() {
+                let (ox, oy) = (x as usize * 3 + idx % 3, y as usize * 3 + idx / 3);
+                out.set(ox, oy, color);
+            }
+        }
+    }
+
+    out
+}
+
+fn crt(image: &Image, scale: usize) -> Image {
+    /// How strongly the screen bulges
+    const CURVATURE: f32 = 0.08;
+
+    let mut out = Image::new(image.width * scale, image.height * scale);
+    let (width, height) = (out.width as f32, out.height as f32);
+
+    for y in 0..out.height {
+        for x in 0..out.width {
+            // position relative to the center, from -1.0 to 1.0
+            let u = (x as f32 + 0.5) / width * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / height * 2.0 - 1.0;
+
+            let su = u * (1.0 + CURVATURE * v * v);
+            let sv = v * (1.0 + CURVATURE * u * u);
+            if su.abs() > 1.0 || sv.abs() > 1.0 {
+                // outside of the curved screen, leave it black
+                continue;
+            }
+
+            let sx = ((su + 1.0) / 2.0 * image.width as f32) as usize;
+            let sy = ((sv + 1.0) / 2.0 * image.height as f32) as usize;
+            let color = image.get(sx.min(image.width - 1), sy.min(image.height - 1));
+
+            let vignette = 1.0 - 0.3 * (su * su + sv * sv) / 2.0;
+            let scanline = if is_gap(y, scale) { 0.6 } else { 1.0 };
+
+            out.set(x, y, color.map(|c| (c as f32 * vignette * scanline) as u8));
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ON: [u8; 3] = [255, 255, 255];
+    const OFF: [u8; 3] = [0, 0, 0];
+
+    /// A diagonal line going from top left to bottom right
+    fn diagonal() -> Image {
+        let mut image = Image::new(3, 3);
+        for i in 0..3 {
+            image.set(i, i, ON);
+        }
+
+        image
+    }
+
+    #[test]
+    fn scale2x_smooths_diagonals() {
+        let out = scale2x(&diagonal());
+
+        assert_eq!((out.width, out.height), (6, 6));
+        // the corner between (0, 0) and (1, 1) gets filled in
+        assert_eq!(out.get(2, 1), ON);
+        assert_eq!(out.get(1, 2), ON);
+        // away from the line nothing changes
+        assert_eq!(out.get(5, 0), OFF);
+    }
+
+    #[test]
+    fn scale3x_keeps_flat_areas() {
+        let mut image = Image::new(2, 2);
+        image.pixels.fill(200);
+
+        let out = scale3x(&image);
+
+        assert_eq!((out.width, out.height), (6, 6));
+        assert!(out.pixels.iter().all(|&c| c == 200));
+    }
+
+    #[test]
+    fn scanlines_darken_last_row() {
+        let mut image = Image::new(1, 1);
+        image.set(0, 0, ON);
+
+        let out = Filter::Scanlines.apply(&image, 4);
+
+        assert_eq!(out.get(0, 0), ON);
+        assert_eq!(out.get(0, 3), [127, 127, 127]);
+    }
+
+    #[test]
+    fn grid_gaps() {
+        let mut image = Image::new(2, 1);
+        image.pixels.fill(255);
+
+        let out = Filter::Grid.apply(&image, 3);
+
+        assert_eq!(out.get(0, 0), ON);
+        assert_eq!(out.get(2, 0), [63, 63, 63]);
+        assert_eq!(out.get(0, 2), [63, 63, 63]);
+    }
+
+    #[test]
+    fn crt_corners_are_black() {
+        let mut image = Image::new(4, 4);
+        image.pixels.fill(255);
+
+        let out = Filter::Crt.apply(&image, 8);
+
+        assert_eq!(out.get(0, 0), OFF);
+        assert_ne!(out.get(16, 16), OFF);
+    }
+
+    #[test]
+    fn cycle_filters() {
+        assert_eq!(Filter::None.next(), Filter::Scale2x);
+        assert_eq!(Filter::Crt.next(), Filter::None);
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use color_eyre::{
+    eyre::{eyre, Context as _},
+    Result,
+};
+use sdl2::{
+    controller::{Axis, Button, GameController},
+    event::Event,
+    GameControllerSubsystem, Sdl,
+};
+
+use chirp_8::machine::Machine;
+
+use crate::keymap::parse_binding;
+
+/// How far a stick or trigger has to be pushed to count as pressed
+const DEAD_ZONE: i16 = i16::MAX / 2;
+
+/// A button, or a stick or trigger pushed in one direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Input {
+    Button(Button),
+    /// The axis pushed towards its positive (right/down) or negative end
+    Axis(Axis, bool),
+}
+
+/// Which controller inputs press each keypad key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GamepadMap(HashMap<Input, u8>);
+
+impl GamepadMap {
+    /// Loads the mapping for a ROM: `path` if given, otherwise the ROM's path with a
+    /// `.gamepad` extension if that file exists, otherwise the default mapping.
+    pub fn load(path: Option<&Path>, rom: &Path) -> Result<Self> {
+        let per_rom = rom.with_extension("gamepad");
+        let path = match path {
+            Some(path) => path,
+            None if per_rom.is_file() => &per_rom,
+            None => return Ok(Self::default()),
+        };
+
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+
+        Self::parse(&text).wrap_err_with(|| format!("invalid gamepad mapping {}", path.display()))
+    }
+
+    /// Parses a mapping file, the same format as keymap files with the names SDL uses in
+    /// controller mappings: `a`, `b`, `x`, `y`, `back`, `guide`, `start`, `leftstick`,
+    /// `rightstick`, `leftshoulder`, `rightshoulder`, `dpup`, `dpdown`, `dpleft`, `dpright`,
+    /// the stick directions `leftx-`, `leftx+`, `lefty-`, `lefty+` (and `right...`) and the
+    /// triggers `lefttrigger` and `righttrigger`. Keypad keys that aren't bound keep the
+    /// default inputs.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut map = Self::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            map.bind(line)
+                .map_err(|err| eyre!("line {}: {err}", number + 1))?;
+        }
+
+        Ok(map)
+    }
+
+    fn bind(&mut self, binding: &str) -> Result<()> {
+        let (key, names) = parse_binding(binding)?;
+
+        let inputs = names
+            .into_iter()
+            .map(|name| input_from_name(name).ok_or_else(|| eyre!("unknown input `{name}`")))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.0.retain(|_, bound| *bound != key);
+        self.0.extend(inputs.into_iter().map(|input| (input, key)));
+
+        Ok(())
+    }
+}
+
+impl Default for GamepadMap {
+    /// The D-pad and left stick on 5/8/7/9, which most games move with, and the face
+    /// buttons on 6, 4, A and B.
+    fn default() -> Self {
+        let mut map = Self(HashMap::new());
+        for binding in [
+            "5 = dpup lefty-",
+            "8 = dpdown lefty+",
+            "7 = dpleft leftx-",
+            "9 = dpright leftx+",
+            "6 = a",
+            "4 = b",
+            "A = x",
+            "B = y",
+        ] {
+            map.bind(binding).unwrap();
+        }
+
+        map
+    }
+}
+
+/// Connected controllers, opened and closed as they are plugged in and out
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    /// Open controllers by their joystick id, closed when dropped
+    controllers: HashMap<u32, GameController>,
+    map: GamepadMap,
+    /// Stick directions and triggers currently past the dead zone
+    pushed: HashSet<Input>,
+}
+
+impl Gamepads {
+    pub fn new(context: &Sdl, map: GamepadMap) -> Result<Self> {
+        let subsystem = context
+            .game_controller()
+            .map_err(|_| eyre!("failed to initialize game controllers"))?;
+
+        Ok(Self {
+            subsystem,
+            controllers: HashMap::new(),
+            map,
+            pushed: HashSet::new(),
+        })
+    }
+
+    /// Handles controller events, updating the held keypad keys. Controllers that are already
+    /// connected at startup are reported as added too.
+    pub fn handle(&mut self, event: &Event, machine: &mut Machine) {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => match self.subsystem.open(which) {
+                Ok(controller) => {
+                    println!("connected {}", controller.name());
+                    self.controllers
+                        .insert(controller.instance_id(), controller);
+                }
+                Err(err) => eprintln!("warning: failed to open game controller: {err}"),
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(controller) = self.controllers.remove(&which) {
+                    println!("disconnected {}", controller.name());
+                }
+            }
+            Event::ControllerButtonDown { button, .. } => {
+                self.set(Input::Button(button), true, machine)
+            }
+            Event::ControllerButtonUp { button, .. } => {
+                self.set(Input::Button(button), false, machine)
+            }
+            Event::ControllerAxisMotion { axis, value, .. } => {
+                // only transitions change keys, so the keyboard can hold them too
+                for positive in [true, false] {
+                    let input = Input::Axis(axis, positive);
+                    let pushed = match positive {
+                        true => value > DEAD_ZONE,
+                        false => value < -DEAD_ZONE,
+                    };
+
+                    let changed = match pushed {
+                        true => self.pushed.insert(input),
+                        false => self.pushed.remove(&input),
+                    };
+                    if changed {
+                        self.set(input, pushed, machine);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn set(&self, input: Input, pressed: bool, machine: &mut Machine) {
+        if let Some(&key) = self.map.0.get(&input) {
+            machine.set_key(key, pressed);
+        }
+    }
+}
+
+fn input_from_name(name: &str) -> Option<Input> {
+    let button = match name.to_ascii_lowercase().as_str() {
+        "a" => Button::A,
+        "b" => Button::B,
+        "x" => Button::X,
+        "y" => Button::Y,
+        "back" => Button::Back,
+        "guide" => Button::Guide,
+        "start" => Button::Start,
+        "leftstick" => Button::LeftStick,
+        "rightstick" => Button::RightStick,
+        "leftshoulder" => Button::LeftShoulder,
+        "rightshoulder" => Button::RightShoulder,
+        "dpup" => Button::DPadUp,
+        "dpdown" => Button::DPadDown,
+        "dpleft" => Button::DPadLeft,
+        "dpright" => Button::DPadRight,
+        "lefttrigger" => return Some(Input::Axis(Axis::TriggerLeft, true)),
+        "righttrigger" => return Some(Input::Axis(Axis::TriggerRight, true)),
+        name => {
+            let (axis, positive) = match name.strip_suffix('+') {
+                Some(axis) => (axis, true),
+                None => (name.strip_suffix('-')?, false),
+            };
+            let axis = match axis {
+                "leftx" => Axis::LeftX,
+                "lefty" => Axis::LeftY,
+                "rightx" => Axis::RightX,
+                "righty" => Axis::RightY,
+                _ => return None,
+            };
+
+            return Some(Input::Axis(axis, positive));
+        }
+    };
+
+    Some(Input::Button(button))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mapping_file() {
+        let map = GamepadMap::parse(
+            "# jump with the shoulder buttons\n\
+             6 = leftshoulder RightShoulder righttrigger\n\
+             F = rightx+\n",
+        )
+        .unwrap();
+
+        assert_eq!(map.0.get(&Input::Button(Button::LeftShoulder)), Some(&6));
+        assert_eq!(map.0.get(&Input::Axis(Axis::TriggerRight, true)), Some(&6));
+        assert_eq!(map.0.get(&Input::Axis(Axis::RightX, true)), Some(&0xF));
+        // replaced
+        assert_eq!(map.0.get(&Input::Button(Button::A)), None);
+        // kept
+        assert_eq!(map.0.get(&Input::Button(Button::DPadUp)), Some(&5));
+    }
+
+    #[test]
+    fn invalid_mapping_file() {
+        assert!(GamepadMap::parse("6 = z").is_err());
+        assert!(GamepadMap::parse("6 = leftz+").is_err());
+        assert!(GamepadMap::parse("6 = leftx").is_err());
+        assert!(GamepadMap::parse("G = a").is_err());
+        assert!(GamepadMap::parse("6 = é").is_err());
+        assert!(GamepadMap::parse("6 = leftxé").is_err());
+    }
+
+    #[test]
+    fn stick_directions() {
+        let map = GamepadMap::default();
+
+        assert_eq!(map.0.get(&Input::Axis(Axis::LeftX, false)), Some(&7));
+        assert_eq!(map.0.get(&Input::Axis(Axis::LeftY, true)), Some(&8));
+    }
+}
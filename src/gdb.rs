@@ -0,0 +1,655 @@
+use std::{
+    io::{ErrorKind, Read as _, Write as _},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+};
+
+use color_eyre::{eyre::Context as _, Result};
+
+use chirp_8::{
+    machine::Machine,
+    memory::{RegIdx, Watchpoint},
+};
+
+use crate::run_control::{RunControl, Stop};
+
+/// V0 - VF, I, PC and SP, the numbering GDB uses in `p`/`P` packets
+const REGISTERS: u8 = 19;
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// Lets GDB control the machine over the remote serial protocol. Multi-byte registers are sent
+/// little endian, which is what GDB assumes for a target without a known architecture.
+/// Breakpoints are checked before every instruction, so software
+/// and hardware ones are the same, and watchpoints use the memory's.
+pub struct GdbStub {
+    /// Until GDB connects, the machine waits meanwhile
+    listener: Option<TcpListener>,
+    /// `None` once GDB detached, then the machine just runs
+    stream: Option<TcpStream>,
+    /// Received bytes that aren't a complete packet yet
+    input: Vec<u8>,
+    control: RunControl,
+    killed: bool,
+}
+
+impl GdbStub {
+    /// Listens for GDB on `port` on localhost without blocking, [`GdbStub::poll`] accepts the
+    /// connection. The machine stays paused before the first instruction until GDB tells it
+    /// otherwise.
+    pub fn listen(port: u16) -> Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .wrap_err_with(|| format!("failed to listen on port {port}"))?;
+        listener.set_nonblocking(true)?;
+        println!("waiting for GDB on {}", listener.local_addr()?);
+
+        Ok(Self {
+            listener: Some(listener),
+            stream: None,
+            input: Vec::new(),
+            control: RunControl::new(true),
+            killed: false,
+        })
+    }
+
+    #[cfg(test)]
+    fn new(stream: TcpStream) -> Result<Self> {
+        Ok(Self {
+            stream: Some(connected(stream)?),
+            listener: None,
+            input: Vec::new(),
+            control: RunControl::new(true),
+            killed: false,
+        })
+    }
+
+    /// Whether GDB killed the program, then the emulator should quit.
+    pub fn killed(&self) -> bool {
+        self.killed
+    }
+
+    /// Called before every instruction, returns whether it should be executed. Packets are
+    /// handled here.
+    pub fn poll(&mut self, machine: &mut Machine) -> bool {
+        if let Some(listener) = &self.listener {
+            match listener.accept().and_then(|(stream, addr)| {
+                println!("GDB connected from {addr}");
+                connected(stream)
+            }) {
+                Ok(stream) => {
+                    self.stream = Some(stream);
+                    self.listener = None;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => eprintln!("warning: failed to accept a connection from GDB: {err}"),
+            }
+            return false;
+        }
+
+        self.receive(machine);
+        if self.stream.is_none() {
+            return true;
+        }
+
+        if self.control.before_step(machine).is_some() {
+            self.send(&format!("S{SIGTRAP:02x}"));
+        }
+        !self.control.paused()
+    }
+
+    /// Called after every instruction with its result, `pc` is where it started. Errors
+    /// stop the machine at the failing instruction, unless GDB is gone.
+    pub fn after_step(&mut self, machine: &mut Machine, pc: u16, result: Result<()>) -> Result<()> {
+        let stop = self.control.after_step(machine, pc);
+        if self.stream.is_none() {
+            return result;
+        }
+
+        if let Err(err) = result {
+            eprintln!("error: {err}");
+            machine.pc = pc;
+            self.stop(format!("S{SIGILL:02x}"));
+        } else if let Some(Stop::Watch { access, .. }) = stop {
+            let access_watch = machine.memory.watchpoints.iter().any(|watch| {
+                watch.read && watch.write && (watch.start..=watch.end).contains(&access.addr)
+            });
+            let kind = match (access_watch, access.write) {
+                (true, _) => "awatch",
+                (false, true) => "watch",
+                (false, false) => "rwatch",
+            };
+            self.send(&format!("T{SIGTRAP:02x}{kind}:{:x};", access.addr));
+        }
+
+        Ok(())
+    }
+
+    fn stop(&mut self, reply: String) {
+        self.control.pause();
+        self.send(&reply);
+    }
+
+    /// Reads what's there without blocking and handles all complete packets.
+    fn receive(&mut self, machine: &mut Machine) {
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+
+        let mut buffer = [0; 1024];
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => return self.detach(machine),
+                Ok(len) => self.input.extend_from_slice(&buffer[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => {
+                    eprintln!("warning: lost the connection to GDB: {err}");
+                    return self.detach(machine);
+                }
+            }
+        }
+
+        while let Some(packet) = self.next_packet() {
+            if let Some(reply) = self.handle(&packet, machine) {
+                self.send(&reply);
+            }
+            if self.stream.is_none() {
+                break;
+            }
+        }
+    }
+
+    /// Takes the next packet out of the input and acknowledges it, an interrupt is `\x03`.
+    fn next_packet(&mut self) -> Option<String> {
+        loop {
+            // acknowledgements are skipped, packets are never sent again
+            let start = self.input.iter().position(|&b| b == b'$' || b == 0x03)?;
+            self.input.drain(..start);
+            if self.input[0] == 0x03 {
+                self.input.remove(0);
+                return Some("\x03".to_owned());
+            }
+
+            let end = self.input.iter().position(|&b| b == b'#')?;
+            let checksum = self.input.get(end + 1..end + 3)?;
+            let checksum = std::str::from_utf8(checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            let data = String::from_utf8_lossy(&self.input[1..end]).into_owned();
+            self.input.drain(..end + 3);
+
+            if checksum == Some(sum(data.as_bytes())) {
+                self.write(b"+");
+                return Some(data);
+            }
+            self.write(b"-");
+        }
+    }
+
+    /// Replies to a packet, `None` if the reply comes when the machine stops.
+    fn handle(&mut self, packet: &str, machine: &mut Machine) -> Option<String> {
+        let Some(command) = packet.chars().next() else {
+            return Some(String::new());
+        };
+        let args = &packet[command.len_utf8()..];
+
+        let reply = match command {
+            '\x03' if self.control.paused() => return None,
+            '\x03' => {
+                self.stop(format!("S{SIGINT:02x}"));
+                return None;
+            }
+            '?' => format!("S{SIGTRAP:02x}"),
+            'g' => (0..REGISTERS)
+                .map(|reg| register(machine, reg).unwrap_or_default())
+                .collect(),
+            'G' => ok(set_registers(machine, args)),
+            'p' => parse_register(args)
+                .and_then(|reg| register(machine, reg))
+                .unwrap_or_else(error),
+            'P' => ok(args
+                .split_once('=')
+                .and_then(|(reg, value)| set_register(machine, parse_register(reg)?, value))),
+            'm' => read_memory(machine, args).unwrap_or_else(error),
+            'M' => ok(write_memory(machine, args)),
+            'c' | 's' => {
+                if !args.is_empty() {
+                    let Some(pc) = parse_hex(args) else {
+                        return Some(error());
+                    };
+                    machine.pc = pc;
+                }
+                match command {
+                    'c' => self.control.resume(machine),
+                    _ => self.control.step(1),
+                }
+                return None;
+            }
+            'Z' | 'z' => match self.set_breakpoint(machine, args, command == 'Z') {
+                Some(true) => "OK".to_owned(),
+                Some(false) => String::new(),
+                None => error(),
+            },
+            'D' => {
+                self.send("OK");
+                self.detach(machine);
+                return None;
+            }
+            'k' => {
+                self.killed = true;
+                self.detach(machine);
+                return None;
+            }
+            'H' | 'T' => "OK".to_owned(),
+            'q' => query(args),
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    /// Handles `Z`/`z` packets, `Some(false)` for unsupported kinds.
+    fn set_breakpoint(&mut self, machine: &mut Machine, args: &str, insert: bool) -> Option<bool> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?;
+        let addr = parse_hex(fields.next()?)?;
+        let len = parse_hex(fields.next()?)?;
+
+        match kind {
+            // software and hardware breakpoints
+            "0" | "1" => {
+                let breakpoints = self.control.breakpoints_mut();
+                match insert {
+                    true => breakpoints.insert(addr, None),
+                    false => breakpoints.remove(&addr),
+                };
+            }
+            // write, read and access watchpoints
+            "2" | "3" | "4" => {
+                let watchpoint = Watchpoint {
+                    start: addr,
+                    end: addr.checked_add(len.checked_sub(1)?)?,
+                    read: kind != "2",
+                    write: kind != "3",
+                };
+                let watchpoints = &mut machine.memory.watchpoints;
+                match insert {
+                    true => watchpoints.push(watchpoint),
+                    false => watchpoints.retain(|watch| *watch != watchpoint),
+                }
+            }
+            _ => return Some(false),
+        }
+
+        Some(true)
+    }
+
+    /// GDB is gone, the machine runs without breakpoints from now on.
+    fn detach(&mut self, machine: &mut Machine) {
+        self.stream = None;
+        self.control.breakpoints_mut().clear();
+        machine.memory.watchpoints.clear();
+    }
+
+    fn send(&mut self, data: &str) {
+        let packet = format!("${data}#{:02x}", sum(data.as_bytes()));
+        self.write(packet.as_bytes());
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+
+        if let Err(err) = stream.write_all(bytes) {
+            eprintln!("warning: lost the connection to GDB: {err}");
+            self.stream = None;
+        }
+    }
+}
+
+/// Sets up an accepted connection for polling
+fn connected(stream: TcpStream) -> std::io::Result<TcpStream> {
+    stream.set_nonblocking(true)?;
+    stream.set_nodelay(true)?;
+
+    Ok(stream)
+}
+
+fn sum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+fn parse_hex(hex: &str) -> Option<u16> {
+    u16::from_str_radix(hex, 16).ok()
+}
+
+fn parse_register(hex: &str) -> Option<u8> {
+    u8::from_str_radix(hex, 16).ok()
+}
+
+fn ok(result: Option<()>) -> String {
+    result.map_or_else(error, |()| "OK".to_owned())
+}
+
+fn error() -> String {
+    "E01".to_owned()
+}
+
+/// Size of a register in bytes
+fn register_size(reg: u8) -> usize {
+    match reg {
+        16 | 17 => 2,
+        _ => 1,
+    }
+}
+
+fn register(machine: &Machine, reg: u8) -> Option<String> {
+    let value = match reg {
+        0..=15 => machine.regs[RegIdx::new(reg)] as u16,
+        16 => machine.index,
+        17 => machine.pc,
+        18 => machine.stack.len() as u16,
+        _ => return None,
+    };
+
+    Some(match register_size(reg) {
+        2 => format!("{:04x}", value.swap_bytes()),
+        _ => format!("{value:02x}"),
+    })
+}
+
+fn set_register(machine: &mut Machine, reg: u8, hex: &str) -> Option<()> {
+    if hex.len() != register_size(reg) * 2 {
+        return None;
+    }
+    let value = match register_size(reg) {
+        2 => parse_hex(hex)?.swap_bytes(),
+        _ => parse_hex(hex)?,
+    };
+
+    match reg {
+        0..=15 => machine.regs[RegIdx::new(reg)] = value as u8,
+        16 => machine.index = value,
+        17 => machine.pc = value,
+        // the stack keeps its return addresses up to the new depth
+        18 => machine.stack.resize(value as usize, 0),
+        _ => return None,
+    }
+
+    Some(())
+}
+
+fn set_registers(machine: &mut Machine, mut hex: &str) -> Option<()> {
+    for reg in 0..REGISTERS {
+        let (value, rest) = hex.split_at_checked(register_size(reg) * 2)?;
+        set_register(machine, reg, value)?;
+        hex = rest;
+    }
+
+    Some(())
+}
+
+/// `addr,len`, `None` unless all of it is in memory
+fn memory_range(machine: &Machine, args: &str) -> Option<(u16, u16)> {
+    let (addr, len) = args.split_once(',')?;
+    let (addr, len) = (parse_hex(addr)?, parse_hex(len)?);
+
+    (machine.memory.peek_slice(addr, len).len() == len as usize).then_some((addr, len))
+}
+
+fn read_memory(machine: &Machine, args: &str) -> Option<String> {
+    let (addr, len) = memory_range(machine, args)?;
+    let bytes = machine.memory.peek_slice(addr, len);
+
+    Some(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+fn write_memory(machine: &mut Machine, args: &str) -> Option<()> {
+    let (range, data) = args.split_once(':')?;
+    let (addr, len) = memory_range(machine, range)?;
+    if data.len() != len as usize * 2 {
+        return None;
+    }
+
+    for offset in 0..len {
+        let idx = offset as usize * 2;
+        let byte = u8::from_str_radix(data.get(idx..idx + 2)?, 16).ok()?;
+        machine.memory.write_u8(addr + offset, byte).ok()?;
+    }
+    // writes by GDB aren't the program's
+    machine.memory.take_hit();
+
+    Some(())
+}
+
+fn query(args: &str) -> String {
+    if args.starts_with("Supported") {
+        return "PacketSize=4000;qXfer:features:read+".to_owned();
+    }
+    if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+        let Some((offset, len)) = range.split_once(',') else {
+            return error();
+        };
+        let (Some(offset), Some(len)) = (parse_hex(offset), parse_hex(len)) else {
+            return error();
+        };
+
+        let xml = target_xml();
+        let start = (offset as usize).min(xml.len());
+        let end = (start + len as usize).min(xml.len());
+        let more = if end < xml.len() { 'm' } else { 'l' };
+        return format!("{more}{}", &xml[start..end]);
+    }
+
+    match args {
+        "Attached" => "1".to_owned(),
+        "C" => "QC1".to_owned(),
+        "fThreadInfo" => "m1".to_owned(),
+        "sThreadInfo" => "l".to_owned(),
+        _ => String::new(),
+    }
+}
+
+/// Describes the registers, so GDB knows their names and sizes
+fn target_xml() -> String {
+    let regs = (0..16)
+        .map(|idx| format!("<reg name=\"v{idx:x}\" bitsize=\"8\" type=\"uint8\"/>"))
+        .collect::<String>();
+
+    format!(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\">\
+         <feature name=\"org.chirp-8.chip8\">\
+         {regs}\
+         <reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\
+         <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\
+         <reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>\
+         </feature>\
+         </target>"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chirp_8::machine::Quirks;
+
+    use super::*;
+
+    /// A stub connected to the returned client
+    fn connect() -> (GdbStub, TcpStream) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(1)))
+            .unwrap();
+
+        (GdbStub::new(stream).unwrap(), client)
+    }
+
+    /// A stub connected to a client, with a machine run like the emulator loop does
+    struct Session {
+        stub: GdbStub,
+        client: TcpStream,
+        machine: Machine,
+    }
+
+    impl Session {
+        fn new(rom: &[u8]) -> Self {
+            let (stub, client) = connect();
+
+            Self {
+                stub,
+                client,
+                machine: Machine::new(rom, Quirks::default()),
+            }
+        }
+
+        /// Sends `data` as a packet, or raw if it's an interrupt
+        fn send(&mut self, data: &str) {
+            let packet = match data {
+                "\x03" => data.to_owned(),
+                _ => format!("${data}#{:02x}", sum(data.as_bytes())),
+            };
+            self.client.write_all(packet.as_bytes()).unwrap();
+        }
+
+        /// Runs the machine until a packet arrives, returns it without acknowledgements and
+        /// checksum
+        fn reply(&mut self) -> String {
+            let mut received = Vec::new();
+
+            for _ in 0..1000 {
+                if self.stub.poll(&mut self.machine) {
+                    let pc = self.machine.pc;
+                    let result = self.machine.step();
+                    self.stub.after_step(&mut self.machine, pc, result).unwrap();
+                }
+
+                let mut buffer = [0; 256];
+                if let Ok(len) = self.client.read(&mut buffer) {
+                    received.extend_from_slice(&buffer[..len]);
+                }
+
+                let text = String::from_utf8_lossy(&received);
+                let packet = text.trim_start_matches('+').strip_prefix('$');
+                if let Some((data, checksum)) = packet.and_then(|text| text.split_once('#')) {
+                    if checksum.len() == 2 {
+                        return data.to_owned();
+                    }
+                }
+            }
+
+            panic!(
+                "no reply, received {:?}",
+                String::from_utf8_lossy(&received)
+            );
+        }
+
+        fn request(&mut self, data: &str) -> String {
+            self.send(data);
+            self.reply()
+        }
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let mut session = Session::new(&[0x70, 0x01]);
+
+        assert_eq!(session.request("?"), "S05");
+        assert_eq!(
+            session.request("g"),
+            format!("{}00000002{}", "00".repeat(16), "00")
+        );
+        assert_eq!(session.request("P10=0003"), "OK");
+        assert_eq!(session.machine.index, 0x300);
+        assert_eq!(session.request("P3=7f"), "OK");
+        assert_eq!(session.request("p10"), "0003");
+        assert_eq!(session.request("p3"), "7f");
+        assert_eq!(session.request("P3=7"), "E01");
+        assert_eq!(session.request("p13"), "E01");
+
+        assert_eq!(session.request("M300,2:abcd"), "OK");
+        assert_eq!(session.request("m2ff,4"), "00abcd00");
+        assert_eq!(session.request("mfff,2"), "E01");
+
+        assert!(session
+            .request("qSupported:swbreak+")
+            .contains("qXfer:features:read+"));
+        let xml = session.request("qXfer:features:read:target.xml:0,fff");
+        assert!(xml.starts_with("l<?xml") && xml.contains("name=\"pc\""));
+        assert_eq!(session.request("vMustReplyEmpty"), "");
+    }
+
+    #[test]
+    fn waits_for_gdb_without_blocking() {
+        let mut stub = GdbStub::listen(0).unwrap();
+        let addr = stub.listener.as_ref().unwrap().local_addr().unwrap();
+        let mut machine = Machine::new(&[0x70, 0x01], Quirks::default());
+        assert!(!stub.poll(&mut machine));
+
+        let client = TcpStream::connect(addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(1)))
+            .unwrap();
+        for _ in 0..1000 {
+            if stub.stream.is_some() {
+                break;
+            }
+            stub.poll(&mut machine);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let mut session = Session {
+            stub,
+            client,
+            machine,
+        };
+        assert_eq!(session.request("?"), "S05");
+    }
+
+    #[test]
+    fn bad_checksums_are_refused() {
+        let mut session = Session::new(&[0x70, 0x01]);
+
+        session.client.write_all(b"$g#00").unwrap();
+        let mut answer = [0];
+        for _ in 0..1000 {
+            session.stub.poll(&mut session.machine);
+            if session.client.read(&mut answer).is_ok() {
+                break;
+            }
+        }
+        assert_eq!(&answer, b"-");
+    }
+
+    #[test]
+    fn execution() {
+        // I = 300, V0 += 1, BCD of V0 at I, jump back to V0 += 1
+        let mut session = Session::new(&[0xA3, 0x00, 0x70, 0x01, 0xF0, 0x33, 0x12, 0x02]);
+
+        assert_eq!(session.request("s"), "S05");
+        assert_eq!((session.machine.pc, session.machine.cycles), (0x202, 1));
+
+        assert_eq!(session.request("Z1,204,2"), "OK");
+        assert_eq!(session.request("c"), "S05");
+        assert_eq!((session.machine.pc, session.machine.cycles), (0x204, 2));
+        assert_eq!(session.request("z1,204,2"), "OK");
+
+        // the BCD writes the ones digit to 302
+        assert_eq!(session.request("Z2,302,1"), "OK");
+        assert_eq!(session.request("c"), "T05watch:302;");
+        assert_eq!((session.machine.pc, session.machine.cycles), (0x206, 3));
+        assert_eq!(session.request("z2,302,1"), "OK");
+        assert!(session.machine.memory.watchpoints.is_empty());
+
+        // runs until interrupted
+        session.send("c");
+        assert_eq!(session.request("\x03"), "S02");
+        assert!(session.machine.cycles > 3);
+    }
+}
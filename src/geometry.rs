@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+
+use color_eyre::{
+    eyre::{eyre, Context as _},
+    Result,
+};
+
+/// Position and size of the window, remembered between runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Geometry {
+    /// Loads the geometry saved by the last run, if there is one.
+    pub fn load() -> Option<Self> {
+        let text = std::fs::read_to_string(path()?).ok()?;
+
+        Self::parse(&text)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = path().ok_or_else(|| eyre!("no configuration directory found"))?;
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+        }
+
+        std::fs::write(&path, self.to_string())
+            .wrap_err_with(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Parses `<x> <y> <width> <height>`
+    fn parse(text: &str) -> Option<Self> {
+        let mut fields = text.split_whitespace();
+        let mut next = || fields.next()?.parse::<i64>().ok();
+
+        let geometry = Self {
+            x: next()?.try_into().ok()?,
+            y: next()?.try_into().ok()?,
+            width: next()?.try_into().ok()?,
+            height: next()?.try_into().ok()?,
+        };
+
+        (geometry.width > 0 && geometry.height > 0).then_some(geometry)
+    }
+}
+
+impl std::fmt::Display for Geometry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {} {} {}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// `chirp-8/window` in the platform's configuration directory
+fn path() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config.join("chirp-8").join("window"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let geometry = Geometry {
+            x: -20,
+            y: 40,
+            width: 1280,
+            height: 640,
+        };
+
+        assert_eq!(Geometry::parse(&geometry.to_string()), Some(geometry));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(Geometry::parse(""), None);
+        assert_eq!(Geometry::parse("10 10 640"), None);
+        assert_eq!(Geometry::parse("10 10 0 320"), None);
+        assert_eq!(Geometry::parse("10 10 -640 320"), None);
+    }
+}
@@ -0,0 +1,111 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::{eyre::bail, Result};
+
+use chirp_8::{machine::Machine, threaded::Threaded};
+
+use crate::{
+    recorder::{Recorder, FPS},
+    screenshot,
+    sound::Wav,
+    Args, DEFAULT_SCALE,
+};
+
+/// Runs the ROM for `--frames` frames of 1/60 s as fast as possible, without a window. Sound
+/// only goes into a WAV file with `--wav` or `--record-audio`.
+pub fn run(rom: &[u8], args: &Args) -> Result<()> {
+    let mut machine = Machine::new(rom, args.quirks());
+    let frames = args.frames.unwrap_or_default();
+    let scale = args.scale.unwrap_or(DEFAULT_SCALE);
+    let fps = FPS as u64;
+    let palette = args.palette()?;
+
+    let shots = match args.screenshot_at.as_slice() {
+        [] => vec![frames],
+        frames => frames.to_vec(),
+    };
+    if let Some(shot) = shots.iter().find(|&&shot| shot > frames) {
+        bail!("can't take a screenshot after frame {shot}, only {frames} frames are run");
+    }
+    // after `frame` frames
+    let screenshot = |machine: &Machine, frame: u64| match &args.screenshot {
+        Some(path) if shots.contains(&frame) => {
+            let path = match shots.len() {
+                1 => path.clone(),
+                _ => numbered(path, frame),
+            };
+            screenshot::save(&path, &machine.framebuffer, &palette, scale)
+        }
+        _ => Ok(()),
+    };
+
+    let mut recorder = match &args.record {
+        Some(path) => Some(Recorder::start(path, palette, scale)?),
+        None => None,
+    };
+
+    let wav_path = match &args.record {
+        Some(path) if args.record_audio => Some(path.with_extension("wav")),
+        _ => args.wav.clone(),
+    };
+    let mut wav = match wav_path {
+        Some(path) => Some(Wav::create(&path, args.tone(), args.ips, 0)?),
+        None => None,
+    };
+
+    let mut threaded = args.threaded.then(Threaded::new);
+    // instructions the threaded engine ran into the next frame, it only stops between blocks
+    let mut ahead = 0;
+
+    screenshot(&machine, 0)?;
+    for frame in 0..frames {
+        // spread the instructions evenly when the rate isn't a multiple of the frame rate
+        let steps = (args.ips * (frame + 1) / fps - args.ips * frame / fps) as usize;
+        match &mut threaded {
+            Some(threaded) if steps > ahead => {
+                ahead = threaded.run(&mut machine, steps - ahead)? - (steps - ahead);
+            }
+            Some(_) => ahead -= steps,
+            None => {
+                for _ in 0..steps {
+                    machine.step()?;
+                }
+            }
+        }
+
+        if let Some(recorder) = &mut recorder {
+            recorder.frame(&machine.framebuffer)?;
+        }
+        screenshot(&machine, frame + 1)?;
+
+        machine.tick_timers(1);
+
+        let events = machine.take_sound_events();
+        if let Some(wav) = &mut wav {
+            wav.push(&events);
+            wav.write_until(machine.cycles)?;
+        }
+    }
+
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+
+    if let Some(wav) = wav {
+        wav.finish()?;
+    }
+
+    Ok(())
+}
+
+/// `shot.png` becomes `shot-<frame>.png`
+fn numbered(path: &Path, frame: u64) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(format!("-{frame}"));
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+
+    path.with_file_name(name)
+}
@@ -1,3 +1,5 @@
+use std::{collections::BTreeMap, fmt};
+
 use crate::memory::RegIdx;
 
 /// Describes CHIP-8 instructions
@@ -9,6 +11,8 @@ use crate::memory::RegIdx;
 /// - NNN is nibbles 2, 3, 4 (12-bit immediate memory address)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
+    /// 0NNN
+    MachineCall(u16),
     /// 00E0
     ClearScreen,
     /// 1NNN
@@ -86,6 +90,7 @@ impl Instruction {
         Some(match word.nibble1() {
             0 if word.byte2() == 0xE0 => ClearScreen,
             0 if word.byte2() == 0xEE => Return,
+            0 => MachineCall(word.bits12()),
             1 => Jump(word.bits12()),
             2 => Call(word.bits12()),
             3 => SkipEqIm(RegIdx::new(word.nibble2()), word.byte2()),
@@ -148,6 +153,229 @@ impl Instruction {
             _ => return None,
         })
     }
+
+    /// Whether the instruction can change `pc` or write to memory, which ends a basic block
+    pub const fn ends_block(self) -> bool {
+        use Instruction::*;
+
+        matches!(
+            self,
+            MachineCall(_)
+                | Jump(_)
+                | Call(_)
+                | Return
+                | JumpV0(_)
+                | SkipEqIm(..)
+                | SkipNeIm(..)
+                | SkipEq(..)
+                | SkipNe(..)
+                | SkipKeyEq(_)
+                | SkipKeyNe(_)
+                | GetKey(_)
+                | SetBcd(_)
+                | RegStore(_)
+        )
+    }
+}
+
+/// Upper bound on the number of instructions in a basic block, for the execution engines that
+/// compile them
+pub const MAX_BLOCK_LEN: usize = 64;
+
+/// Assembly syntax instructions are written in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "frontend", derive(clap::ValueEnum))]
+pub enum Syntax {
+    /// Mnemonics of Cowgod's technical reference, like `LD V3, 0x12`
+    #[default]
+    Cowgod,
+    /// Octo's assembly language, like `v3 := 0x12`
+    Octo,
+    /// The emulator's own names for the instructions, like `SetIm V3, 0x12`
+    Mnemonic,
+}
+
+impl Instruction {
+    /// Writes the instruction in `syntax`, `{inst}` is the same as `{inst.syntax(Syntax::Cowgod)}`.
+    pub fn syntax(self, syntax: Syntax) -> Formatted<'static> {
+        Formatted {
+            inst: self,
+            syntax,
+            labels: None,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.syntax(Syntax::default()).fmt(f)
+    }
+}
+
+/// An instruction written in some [`Syntax`], see [`Instruction::syntax`]
+#[derive(Debug, Clone, Copy)]
+pub struct Formatted<'a> {
+    inst: Instruction,
+    syntax: Syntax,
+    labels: Option<&'a BTreeMap<u16, String>>,
+}
+
+impl Formatted<'_> {
+    /// Writes addresses that have a label by its name instead.
+    pub fn with_labels(self, labels: &BTreeMap<u16, String>) -> Formatted<'_> {
+        Formatted {
+            inst: self.inst,
+            syntax: self.syntax,
+            labels: Some(labels),
+        }
+    }
+
+    fn addr(&self, addr: u16) -> String {
+        match self.labels.and_then(|labels| labels.get(&addr)) {
+            Some(label) => label.clone(),
+            None => format!("{addr:#05x}"),
+        }
+    }
+
+    fn cowgod(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        match self.inst {
+            MachineCall(nnn) => write!(f, "SYS {}", self.addr(nnn)),
+            ClearScreen => write!(f, "CLS"),
+            Jump(nnn) => write!(f, "JP {}", self.addr(nnn)),
+            Return => write!(f, "RET"),
+            Call(nnn) => write!(f, "CALL {}", self.addr(nnn)),
+            SkipEqIm(vx, nn) => write!(f, "SE {vx}, {nn:#04x}"),
+            SkipNeIm(vx, nn) => write!(f, "SNE {vx}, {nn:#04x}"),
+            SkipEq(vx, vy) => write!(f, "SE {vx}, {vy}"),
+            SkipNe(vx, vy) => write!(f, "SNE {vx}, {vy}"),
+            SetIm(vx, nn) => write!(f, "LD {vx}, {nn:#04x}"),
+            AddIm(vx, nn) => write!(f, "ADD {vx}, {nn:#04x}"),
+            Set(vx, vy) => write!(f, "LD {vx}, {vy}"),
+            Or(vx, vy) => write!(f, "OR {vx}, {vy}"),
+            And(vx, vy) => write!(f, "AND {vx}, {vy}"),
+            Xor(vx, vy) => write!(f, "XOR {vx}, {vy}"),
+            Add(vx, vy) => write!(f, "ADD {vx}, {vy}"),
+            Sub(vx, vy) => write!(f, "SUB {vx}, {vy}"),
+            SubOpp(vx, vy) => write!(f, "SUBN {vx}, {vy}"),
+            Shr(vx, vy) => write!(f, "SHR {vx}, {vy}"),
+            Shl(vx, vy) => write!(f, "SHL {vx}, {vy}"),
+            SetIndex(nnn) => write!(f, "LD I, {}", self.addr(nnn)),
+            JumpV0(nnn) => write!(f, "JP V0, {}", self.addr(nnn)),
+            RandAnd(vx, nn) => write!(f, "RND {vx}, {nn:#04x}"),
+            Draw(vx, vy, n) => write!(f, "DRW {vx}, {vy}, {n}"),
+            SkipKeyEq(vx) => write!(f, "SKP {vx}"),
+            SkipKeyNe(vx) => write!(f, "SKNP {vx}"),
+            GetDelay(vx) => write!(f, "LD {vx}, DT"),
+            SetDelay(vx) => write!(f, "LD DT, {vx}"),
+            SetSound(vx) => write!(f, "LD ST, {vx}"),
+            AddIndex(vx) => write!(f, "ADD I, {vx}"),
+            GetKey(vx) => write!(f, "LD {vx}, K"),
+            IndexCharacter(vx) => write!(f, "LD F, {vx}"),
+            SetBcd(vx) => write!(f, "LD B, {vx}"),
+            RegStore(vx) => write!(f, "LD [I], {vx}"),
+            RegLoad(vx) => write!(f, "LD {vx}, [I]"),
+        }
+    }
+
+    /// Skips are written the way Octo's `if ... then` compiles to them, so the condition is
+    /// the opposite of the skip's.
+    fn octo(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        let v = |reg: RegIdx| format!("v{:x}", reg.as_u8());
+        match self.inst {
+            // Octo has no mnemonic for it, the word is written as two bytes
+            MachineCall(nnn) => write!(f, "{:#04x} {:#04x}", nnn >> 8, nnn & 0xFF),
+            ClearScreen => write!(f, "clear"),
+            Jump(nnn) => write!(f, "jump {}", self.addr(nnn)),
+            Return => write!(f, "return"),
+            Call(nnn) => write!(f, ":call {}", self.addr(nnn)),
+            SkipEqIm(vx, nn) => write!(f, "if {} != {nn:#04x} then", v(vx)),
+            SkipNeIm(vx, nn) => write!(f, "if {} == {nn:#04x} then", v(vx)),
+            SkipEq(vx, vy) => write!(f, "if {} != {} then", v(vx), v(vy)),
+            SkipNe(vx, vy) => write!(f, "if {} == {} then", v(vx), v(vy)),
+            SetIm(vx, nn) => write!(f, "{} := {nn:#04x}", v(vx)),
+            AddIm(vx, nn) => write!(f, "{} += {nn:#04x}", v(vx)),
+            Set(vx, vy) => write!(f, "{} := {}", v(vx), v(vy)),
+            Or(vx, vy) => write!(f, "{} |= {}", v(vx), v(vy)),
+            And(vx, vy) => write!(f, "{} &= {}", v(vx), v(vy)),
+            Xor(vx, vy) => write!(f, "{} ^= {}", v(vx), v(vy)),
+            Add(vx, vy) => write!(f, "{} += {}", v(vx), v(vy)),
+            Sub(vx, vy) => write!(f, "{} -= {}", v(vx), v(vy)),
+            SubOpp(vx, vy) => write!(f, "{} =- {}", v(vx), v(vy)),
+            Shr(vx, vy) => write!(f, "{} >>= {}", v(vx), v(vy)),
+            Shl(vx, vy) => write!(f, "{} <<= {}", v(vx), v(vy)),
+            SetIndex(nnn) => write!(f, "i := {}", self.addr(nnn)),
+            JumpV0(nnn) => write!(f, "jump0 {}", self.addr(nnn)),
+            RandAnd(vx, nn) => write!(f, "{} := random {nn:#04x}", v(vx)),
+            Draw(vx, vy, n) => write!(f, "sprite {} {} {n}", v(vx), v(vy)),
+            SkipKeyEq(vx) => write!(f, "if {} -key then", v(vx)),
+            SkipKeyNe(vx) => write!(f, "if {} key then", v(vx)),
+            GetDelay(vx) => write!(f, "{} := delay", v(vx)),
+            SetDelay(vx) => write!(f, "delay := {}", v(vx)),
+            SetSound(vx) => write!(f, "buzzer := {}", v(vx)),
+            AddIndex(vx) => write!(f, "i += {}", v(vx)),
+            GetKey(vx) => write!(f, "{} := key", v(vx)),
+            IndexCharacter(vx) => write!(f, "i := hex {}", v(vx)),
+            SetBcd(vx) => write!(f, "bcd {}", v(vx)),
+            RegStore(vx) => write!(f, "save {}", v(vx)),
+            RegLoad(vx) => write!(f, "load {}", v(vx)),
+        }
+    }
+
+    fn mnemonic(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        match self.inst {
+            MachineCall(nnn) => write!(f, "MachineCall {}", self.addr(nnn)),
+            ClearScreen => write!(f, "ClearScreen"),
+            Jump(nnn) => write!(f, "Jump {}", self.addr(nnn)),
+            Return => write!(f, "Return"),
+            Call(nnn) => write!(f, "Call {}", self.addr(nnn)),
+            SkipEqIm(vx, nn) => write!(f, "SkipEqIm {vx}, {nn:#04x}"),
+            SkipNeIm(vx, nn) => write!(f, "SkipNeIm {vx}, {nn:#04x}"),
+            SkipEq(vx, vy) => write!(f, "SkipEq {vx}, {vy}"),
+            SkipNe(vx, vy) => write!(f, "SkipNe {vx}, {vy}"),
+            SetIm(vx, nn) => write!(f, "SetIm {vx}, {nn:#04x}"),
+            AddIm(vx, nn) => write!(f, "AddIm {vx}, {nn:#04x}"),
+            Set(vx, vy) => write!(f, "Set {vx}, {vy}"),
+            Or(vx, vy) => write!(f, "Or {vx}, {vy}"),
+            And(vx, vy) => write!(f, "And {vx}, {vy}"),
+            Xor(vx, vy) => write!(f, "Xor {vx}, {vy}"),
+            Add(vx, vy) => write!(f, "Add {vx}, {vy}"),
+            Sub(vx, vy) => write!(f, "Sub {vx}, {vy}"),
+            SubOpp(vx, vy) => write!(f, "SubOpp {vx}, {vy}"),
+            Shr(vx, vy) => write!(f, "Shr {vx}, {vy}"),
+            Shl(vx, vy) => write!(f, "Shl {vx}, {vy}"),
+            SetIndex(nnn) => write!(f, "SetIndex {}", self.addr(nnn)),
+            JumpV0(nnn) => write!(f, "JumpV0 {}", self.addr(nnn)),
+            RandAnd(vx, nn) => write!(f, "RandAnd {vx}, {nn:#04x}"),
+            Draw(vx, vy, n) => write!(f, "Draw {vx}, {vy}, {n}"),
+            SkipKeyEq(vx) => write!(f, "SkipKeyEq {vx}"),
+            SkipKeyNe(vx) => write!(f, "SkipKeyNe {vx}"),
+            GetDelay(vx) => write!(f, "GetDelay {vx}"),
+            SetDelay(vx) => write!(f, "SetDelay {vx}"),
+            SetSound(vx) => write!(f, "SetSound {vx}"),
+            AddIndex(vx) => write!(f, "AddIndex {vx}"),
+            GetKey(vx) => write!(f, "GetKey {vx}"),
+            IndexCharacter(vx) => write!(f, "IndexCharacter {vx}"),
+            SetBcd(vx) => write!(f, "SetBcd {vx}"),
+            RegStore(vx) => write!(f, "RegStore {vx}"),
+            RegLoad(vx) => write!(f, "RegLoad {vx}"),
+        }
+    }
+}
+
+impl fmt::Display for Formatted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.syntax {
+            Syntax::Cowgod => self.cowgod(f),
+            Syntax::Octo => self.octo(f),
+            Syntax::Mnemonic => self.mnemonic(f),
+        }
+    }
 }
 
 /// A simple  helper trait to extract data needed by instructions
@@ -205,6 +433,14 @@ mod tests {
         );
     }
 
+    #[test]
+    fn decode_machine_call() {
+        assert_eq!(
+            Instruction::decode(0x0123).unwrap(),
+            Instruction::MachineCall(0x123)
+        );
+    }
+
     #[test]
     fn decode_jump() {
         assert_eq!(
@@ -244,4 +480,41 @@ mod tests {
             Instruction::Draw(RegIdx::new(10), RegIdx::new(1), 12)
         );
     }
+
+    #[test]
+    fn syntaxes() {
+        let draw = Instruction::decode(0xDA1C).unwrap();
+        assert_eq!(draw.to_string(), "DRW VA, V1, 12");
+        assert_eq!(draw.syntax(Syntax::Octo).to_string(), "sprite va v1 12");
+        assert_eq!(draw.syntax(Syntax::Mnemonic).to_string(), "Draw VA, V1, 12");
+
+        let skip = Instruction::decode(0x3A0F).unwrap();
+        assert_eq!(skip.to_string(), "SE VA, 0x0f");
+        assert_eq!(skip.syntax(Syntax::Octo).to_string(), "if va != 0x0f then");
+
+        let call = Instruction::decode(0x2208).unwrap();
+        let labels = BTreeMap::from([(0x208, "sub_208".to_owned())]);
+        assert_eq!(call.to_string(), "CALL 0x208");
+        assert_eq!(
+            call.syntax(Syntax::Octo).with_labels(&labels).to_string(),
+            ":call sub_208"
+        );
+        assert_eq!(
+            Instruction::Return.syntax(Syntax::Mnemonic).to_string(),
+            "Return"
+        );
+        assert_eq!(
+            call.syntax(Syntax::Mnemonic)
+                .with_labels(&labels)
+                .to_string(),
+            "Call sub_208"
+        );
+        assert_eq!(
+            Instruction::decode(0xF633)
+                .unwrap()
+                .syntax(Syntax::Mnemonic)
+                .to_string(),
+            "SetBcd V6"
+        );
+    }
 }
@@ -0,0 +1,482 @@
+use std::{collections::HashMap, path::Path};
+
+use color_eyre::{
+    eyre::{bail, eyre, Context as _},
+    Result,
+};
+#[cfg(feature = "window")]
+use sdl2::keyboard::{Keycode, Scancode};
+
+/// A keyboard key, either by where it is or by the lowercase character it types on the
+/// active layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    Position(Position),
+    Label(char),
+}
+
+/// Keyboard keys that can be bound by position, named after what they type on a US layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Position {
+    Num1,
+    Num2,
+    Num3,
+    Num4,
+    Q,
+    W,
+    E,
+    R,
+    A,
+    S,
+    D,
+    F,
+    Z,
+    X,
+    C,
+    V,
+    Space,
+    Return,
+    Tab,
+    Backspace,
+    Up,
+    Down,
+    Left,
+    Right,
+    Kp0,
+    Kp1,
+    Kp2,
+    Kp3,
+    Kp4,
+    Kp5,
+    Kp6,
+    Kp7,
+    Kp8,
+    Kp9,
+    KpDivide,
+    KpMultiply,
+    KpMinus,
+    KpPlus,
+    KpEnter,
+    KpPeriod,
+}
+
+#[cfg(feature = "window")]
+impl Position {
+    pub fn from_scancode(scancode: Scancode) -> Option<Self> {
+        use Position::*;
+
+        Some(match scancode {
+            Scancode::Num1 => Num1,
+            Scancode::Num2 => Num2,
+            Scancode::Num3 => Num3,
+            Scancode::Num4 => Num4,
+            Scancode::Q => Q,
+            Scancode::W => W,
+            Scancode::E => E,
+            Scancode::R => R,
+            Scancode::A => A,
+            Scancode::S => S,
+            Scancode::D => D,
+            Scancode::F => F,
+            Scancode::Z => Z,
+            Scancode::X => X,
+            Scancode::C => C,
+            Scancode::V => V,
+            Scancode::Space => Space,
+            Scancode::Return => Return,
+            Scancode::Tab => Tab,
+            Scancode::Backspace => Backspace,
+            Scancode::Up => Up,
+            Scancode::Down => Down,
+            Scancode::Left => Left,
+            Scancode::Right => Right,
+            Scancode::Kp0 => Kp0,
+            Scancode::Kp1 => Kp1,
+            Scancode::Kp2 => Kp2,
+            Scancode::Kp3 => Kp3,
+            Scancode::Kp4 => Kp4,
+            Scancode::Kp5 => Kp5,
+            Scancode::Kp6 => Kp6,
+            Scancode::Kp7 => Kp7,
+            Scancode::Kp8 => Kp8,
+            Scancode::Kp9 => Kp9,
+            Scancode::KpDivide => KpDivide,
+            Scancode::KpMultiply => KpMultiply,
+            Scancode::KpMinus => KpMinus,
+            Scancode::KpPlus => KpPlus,
+            Scancode::KpEnter => KpEnter,
+            Scancode::KpPeriod => KpPeriod,
+            _ => return None,
+        })
+    }
+}
+
+/// The 1234/QWER/ASDF/ZXCV block, by position so it's the same keys on every layout
+const BLOCK: [Position; 16] = [
+    Position::Num1,
+    Position::Num2,
+    Position::Num3,
+    Position::Num4,
+    Position::Q,
+    Position::W,
+    Position::E,
+    Position::R,
+    Position::A,
+    Position::S,
+    Position::D,
+    Position::F,
+    Position::Z,
+    Position::X,
+    Position::C,
+    Position::V,
+];
+
+/// What the keys of [`BLOCK`] type on the layouts the presets are named after, with and
+/// without Shift where they differ. A terminal only reports characters, this is how the
+/// terminal frontend finds the keys.
+const LAYOUTS: [(&str, [&str; 16]); 4] = [
+    (
+        "qwerty",
+        [
+            "1", "2", "3", "4", "q", "w", "e", "r", "a", "s", "d", "f", "z", "x", "c", "v",
+        ],
+    ),
+    (
+        "azerty",
+        [
+            "&1", "é2", "\"3", "'4", "a", "z", "e", "r", "q", "s", "d", "f", "w", "x", "c", "v",
+        ],
+    ),
+    (
+        "qwertz",
+        [
+            "1", "2", "3", "4", "q", "w", "e", "r", "a", "s", "d", "f", "y", "x", "c", "v",
+        ],
+    ),
+    (
+        "dvorak",
+        [
+            "1", "2", "3", "4", "'", ",", ".", "p", "a", "o", "e", "u", ";", "q", "j", "k",
+        ],
+    ),
+];
+
+const NUMPAD: [Position; 16] = [
+    Position::Kp0,
+    Position::Kp1,
+    Position::Kp2,
+    Position::Kp3,
+    Position::Kp4,
+    Position::Kp5,
+    Position::Kp6,
+    Position::Kp7,
+    Position::Kp8,
+    Position::Kp9,
+    Position::KpDivide,
+    Position::KpMultiply,
+    Position::KpMinus,
+    Position::KpPlus,
+    Position::KpEnter,
+    Position::KpPeriod,
+];
+
+/// Keys labeled with the hex digits, wherever the layout puts them
+const HEX: [char; 16] = [
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f',
+];
+
+/// Which keyboard keys press each of the 16 keypad keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: HashMap<Key, u8>,
+    /// What the keys of [`BLOCK`] type, for [`Keymap::get_char`]
+    layout: [&'static str; 16],
+}
+
+impl Keymap {
+    /// Built-in layouts, selectable by name with `--keymap`. The block of keys the layouts
+    /// are named after is the same physical keys on all of them, they only differ in the
+    /// characters those keys type for the terminal frontend.
+    pub const PRESETS: [&'static str; 6] =
+        ["qwerty", "azerty", "qwertz", "dvorak", "numpad", "hex"];
+
+    /// Looks up a preset by name, otherwise loads a keymap file from that path.
+    pub fn load(keymap: &str) -> Result<Self> {
+        if let Some(keymap) = Self::preset(keymap) {
+            return Ok(keymap);
+        }
+
+        let path = Path::new(keymap);
+        let text = std::fs::read_to_string(path).wrap_err_with(|| {
+            let names = Self::PRESETS.join(", ");
+            format!("{keymap} is neither a preset ({names}) nor a readable keymap file")
+        })?;
+
+        Self::parse(&text).wrap_err_with(|| format!("invalid keymap file {}", path.display()))
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        let layout = LAYOUTS
+            .iter()
+            .find(|(layout, _)| *layout == name)
+            .map(|(_, layout)| *layout);
+        let keys = match (name, layout) {
+            (_, Some(_)) => BLOCK.map(Key::Position),
+            ("numpad", _) => NUMPAD.map(Key::Position),
+            ("hex", _) => HEX.map(Key::Label),
+            _ => return None,
+        };
+
+        let keys = keys
+            .into_iter()
+            .enumerate()
+            .map(|(idx, key)| (key, idx as u8))
+            .collect();
+
+        Some(Self {
+            keys,
+            layout: layout.unwrap_or(LAYOUTS[0].1),
+        })
+    }
+
+    /// Parses a keymap file. Each line is a binding (see [`Keymap::bind`]) or a comment
+    /// starting with `#`. Keypad keys that aren't bound keep the keys of the `qwerty` preset,
+    /// and the terminal frontend assumes a QWERTY layout.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut keymap = Self::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            keymap
+                .bind(line)
+                .map_err(|err| eyre!("line {}: {err}", number + 1))?;
+        }
+
+        Ok(keymap)
+    }
+
+    /// Binds keys with `<keypad key> = <key> [<key>...]`. The keypad key is a hex digit and
+    /// keys are either the character on the key, which depends on the layout, or one of
+    /// `Space`, `Return`, `Tab`, `Backspace`, `Up`, `Down`, `Left`, `Right`, `KP0` - `KP9`,
+    /// `KP/`, `KP*`, `KP-`, `KP+`, `KPEnter` and `KP.` for the numeric keypad. The keys
+    /// replace the keypad key's previous ones.
+    pub fn bind(&mut self, binding: &str) -> Result<()> {
+        let (key, names) = parse_binding(binding)?;
+
+        let keys = names
+            .into_iter()
+            .map(|name| key_from_name(name).ok_or_else(|| eyre!("unknown key `{name}`")))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.keys.retain(|_, bound| *bound != key);
+        self.keys.extend(keys.into_iter().map(|bound| (bound, key)));
+
+        Ok(())
+    }
+
+    /// The keypad key pressed by a keyboard key, bound by its position or its label
+    pub fn get(&self, position: Option<Position>, label: Option<char>) -> Option<u8> {
+        let position = position.and_then(|position| self.keys.get(&Key::Position(position)));
+        let label = label.and_then(|label| self.keys.get(&Key::Label(label)));
+
+        position.or(label).copied()
+    }
+
+    /// The keypad key pressed by typing a character, for frontends that only see characters.
+    /// Positions are found through the preset's layout.
+    pub fn get_char(&self, c: char) -> Option<u8> {
+        let label = c.to_lowercase().next()?;
+        let position = match self.layout.iter().position(|chars| chars.contains(label)) {
+            Some(idx) => Some(BLOCK[idx]),
+            None => named_position(c),
+        };
+
+        self.get(position, Some(label))
+    }
+
+    /// The keypad key pressed by a key of the window
+    #[cfg(feature = "window")]
+    pub fn get_sdl(&self, scancode: Scancode, keycode: Option<Keycode>) -> Option<u8> {
+        // SDL uses the lowercase character as the keycode of character keys
+        let label = keycode.and_then(|keycode| char::from_u32(keycode.into_i32() as u32));
+
+        self.get(Position::from_scancode(scancode), label)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::preset("qwerty").unwrap()
+    }
+}
+
+/// Splits `<keypad key> = <name> [<name>...]` into the keypad key and the names of what
+/// presses it, for keymaps and gamepad mappings.
+pub fn parse_binding(binding: &str) -> Result<(u8, Vec<&str>)> {
+    let (key, names) = binding
+        .split_once('=')
+        .ok_or_else(|| eyre!("expected `<keypad key> = <name> [<name>...]`"))?;
+
+    let key = match u8::from_str_radix(key.trim(), 16) {
+        Ok(key) if key < 16 => key,
+        _ => bail!("`{}` is not a keypad key, expected 0 - F", key.trim()),
+    };
+
+    let names = names.split_whitespace().collect::<Vec<_>>();
+    if names.is_empty() {
+        bail!("nothing bound to keypad key {key:X}");
+    }
+
+    Ok((key, names))
+}
+
+fn key_from_char(c: char) -> Option<Key> {
+    c.to_lowercase().next().map(Key::Label)
+}
+
+/// The key typing a character that's the same on every layout
+fn named_position(c: char) -> Option<Position> {
+    match c {
+        ' ' => Some(Position::Space),
+        '\r' | '\n' => Some(Position::Return),
+        '\t' => Some(Position::Tab),
+        _ => None,
+    }
+}
+
+fn key_from_name(name: &str) -> Option<Key> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return key_from_char(c);
+    }
+
+    // keys that are in the same place on every layout
+    let position = match name.to_ascii_lowercase().as_str() {
+        "space" => Position::Space,
+        "return" => Position::Return,
+        "tab" => Position::Tab,
+        "backspace" => Position::Backspace,
+        "up" => Position::Up,
+        "down" => Position::Down,
+        "left" => Position::Left,
+        "right" => Position::Right,
+        "kp/" => Position::KpDivide,
+        "kp*" => Position::KpMultiply,
+        "kp-" => Position::KpMinus,
+        "kp+" => Position::KpPlus,
+        "kpenter" => Position::KpEnter,
+        "kp." => Position::KpPeriod,
+        kp => match kp.strip_prefix("kp")?.as_bytes() {
+            &[digit @ b'0'..=b'9'] => NUMPAD[(digit - b'0') as usize],
+            _ => return None,
+        },
+    };
+
+    Some(Key::Position(position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets() {
+        for name in Keymap::PRESETS {
+            assert_eq!(Keymap::preset(name).unwrap().keys.len(), 16, "{name}");
+        }
+
+        // the same keys whatever they produce, like `z` on QWERTZ or `&` on AZERTY
+        let qwerty = Keymap::default();
+        assert_eq!(qwerty.get(Some(Position::Num1), Some('&')), Some(0));
+        assert_eq!(qwerty.get(None, Some('z')), None);
+        assert_eq!(Keymap::load("azerty").unwrap().keys, qwerty.keys);
+
+        assert_eq!(
+            Keymap::load("numpad")
+                .unwrap()
+                .get(Some(Position::KpEnter), None),
+            Some(0xE)
+        );
+        // hex digits by label, wherever they are
+        let hex = Keymap::load("hex").unwrap();
+        assert_eq!(hex.get(Some(Position::Q), Some('a')), Some(0xA));
+        assert_eq!(hex.get_char('B'), Some(0xB));
+    }
+
+    #[test]
+    fn layouts_in_the_terminal() {
+        let azerty = Keymap::load("azerty").unwrap();
+        // the keys where QWERTY has 1, Q, A and Z
+        assert_eq!(azerty.get_char('&'), Some(0));
+        assert_eq!(azerty.get_char('1'), Some(0));
+        assert_eq!(azerty.get_char('a'), Some(4));
+        assert_eq!(azerty.get_char('Q'), Some(8));
+        assert_eq!(azerty.get_char('w'), Some(0xC));
+        assert_eq!(azerty.get_char('m'), None);
+
+        let dvorak = Keymap::load("dvorak").unwrap();
+        assert_eq!(dvorak.get_char('o'), Some(9));
+        assert_eq!(dvorak.get_char('s'), None);
+        assert_eq!(Keymap::default().get_char('z'), Some(0xC));
+    }
+
+    #[test]
+    fn multiple_keys() {
+        let mut keymap = Keymap::default();
+        keymap.bind("5 = Up KP8").unwrap();
+
+        assert_eq!(keymap.get(Some(Position::Up), None), Some(5));
+        assert_eq!(keymap.get(Some(Position::Kp8), None), Some(5));
+        // the previous key is unbound
+        assert_eq!(keymap.get(Some(Position::W), Some('w')), None);
+        assert_eq!(keymap.get_char('Q'), Some(4));
+    }
+
+    #[test]
+    fn labels_in_keymap_files() {
+        let mut keymap = Keymap::default();
+        keymap.bind("1 = é").unwrap();
+
+        assert_eq!(keymap.get(Some(Position::Num2), Some('é')), Some(1));
+        assert_eq!(keymap.get(Some(Position::Num2), Some('2')), None);
+        assert_eq!(keymap.get_char('é'), Some(1));
+    }
+
+    #[test]
+    fn parse_keymap_file() {
+        let keymap = Keymap::parse(
+            "# arrows for movement\n\
+             5 = up\n\
+             a = Space\n",
+        )
+        .unwrap();
+
+        assert_eq!(keymap.get(Some(Position::Up), None), Some(5));
+        assert_eq!(keymap.get(Some(Position::Space), None), Some(0xA));
+        assert_eq!(keymap.get(Some(Position::Num1), None), Some(0));
+    }
+
+    #[cfg(feature = "window")]
+    #[test]
+    fn window_keys() {
+        let azerty = Keymap::load("azerty").unwrap();
+
+        assert_eq!(azerty.get_sdl(Scancode::Q, Some(Keycode::A)), Some(4));
+        assert_eq!(azerty.get_sdl(Scancode::Kp8, None), None);
+        let hex = Keymap::load("hex").unwrap();
+        assert_eq!(hex.get_sdl(Scancode::Q, Some(Keycode::A)), Some(0xA));
+    }
+
+    #[test]
+    fn invalid_keymap_file() {
+        assert!(Keymap::parse("5 up").is_err());
+        assert!(Keymap::parse("10 = q").is_err());
+        assert!(Keymap::parse("5 = Hyper").is_err());
+        assert!(Keymap::parse("5 =").is_err());
+        assert!(Keymap::parse("5 = KP10").is_err());
+    }
+}
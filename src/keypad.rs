@@ -0,0 +1,170 @@
+use clap::ValueEnum;
+use color_eyre::{eyre::eyre, Result};
+use sdl2::{rect::Rect, render::Canvas, video::Window};
+
+use chirp_8::memory::Memory;
+
+use crate::palette::{mix, Palette};
+
+/// Keys of the COSMAC VIP keypad, row by row
+const LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// Where the on-screen keypad goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Placement {
+    /// Right of the game, for wide windows
+    Right,
+    /// Below the game, for tall windows and phones
+    Bottom,
+}
+
+impl Placement {
+    /// Splits the window into the game area and the square keypad area. The keypad takes
+    /// a third of the width on the right, or half of the height at the bottom, at most.
+    pub fn split(self, (width, height): (u32, u32)) -> (Rect, Rect) {
+        match self {
+            Placement::Right => {
+                let side = (width / 3).min(height).max(1);
+                let keypad = Rect::new(
+                    (width - side) as i32,
+                    ((height - side) / 2) as i32,
+                    side,
+                    side,
+                );
+
+                (
+                    Rect::new(0, 0, (width - side).max(1), height.max(1)),
+                    keypad,
+                )
+            }
+            Placement::Bottom => {
+                let side = (height / 2).min(width).max(1);
+                let keypad = Rect::new(
+                    ((width - side) / 2) as i32,
+                    (height - side) as i32,
+                    side,
+                    side,
+                );
+
+                (
+                    Rect::new(0, 0, width.max(1), (height - side).max(1)),
+                    keypad,
+                )
+            }
+        }
+    }
+
+    /// Window size that fits the game at `game` size next to the keypad
+    pub fn window_size(self, (width, height): (u32, u32)) -> (u32, u32) {
+        match self {
+            Placement::Right => (width + height, height),
+            Placement::Bottom => (width, height + width / 2),
+        }
+    }
+}
+
+/// The keypad key at a position in the window, if any
+pub fn key_at(area: Rect, x: i32, y: i32) -> Option<u8> {
+    if !area.contains_point((x, y)) {
+        return None;
+    }
+
+    let cell = area.width() as i32 / 4;
+    let (column, row) = ((x - area.x()) / cell.max(1), (y - area.y()) / cell.max(1));
+
+    LAYOUT.get(row as usize)?.get(column as usize).copied()
+}
+
+/// Draws the keypad, held keys are filled with the foreground color.
+pub fn draw(
+    canvas: &mut Canvas<Window>,
+    area: Rect,
+    keys: &[bool; 16],
+    palette: &Palette,
+) -> Result<()> {
+    let cell = area.width() / 4;
+    // room between keys, and the size of a pixel of the labels
+    let gap = (cell / 12).max(1);
+    let pixel = (cell / 10).max(1);
+
+    for (row, keys_in_row) in LAYOUT.iter().enumerate() {
+        for (column, &key) in keys_in_row.iter().enumerate() {
+            let x = area.x() + (column as u32 * cell) as i32;
+            let y = area.y() + (row as u32 * cell) as i32;
+            let held = keys[key as usize];
+
+            let (face, label) = match held {
+                true => (palette.0[1], palette.0[0]),
+                false => (mix(palette.0[0], palette.0[1], 0.2), palette.0[1]),
+            };
+
+            let button = Rect::new(
+                x + gap as i32,
+                y + gap as i32,
+                cell.saturating_sub(2 * gap).max(1),
+                cell.saturating_sub(2 * gap).max(1),
+            );
+            canvas.set_draw_color(face);
+            canvas
+                .fill_rect(button)
+                .map_err(|_| eyre!("failed to draw the keypad"))?;
+
+            // the label is the font sprite, 4x5 pixels centered on the key
+            let sprite = &Memory::FONT[key as usize * 5..key as usize * 5 + 5];
+            let left = x + (cell as i32 - 4 * pixel as i32) / 2;
+            let top = y + (cell as i32 - 5 * pixel as i32) / 2;
+
+            canvas.set_draw_color(label);
+            for (dy, bits) in sprite.iter().enumerate() {
+                for dx in (0..4).filter(|dx| bits & (0x80 >> dx) != 0) {
+                    let rect = Rect::new(
+                        left + dx * pixel as i32,
+                        top + dy as i32 * pixel as i32,
+                        pixel,
+                        pixel,
+                    );
+                    canvas
+                        .fill_rect(rect)
+                        .map_err(|_| eyre!("failed to draw the keypad"))?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_window() {
+        let (game, keypad) = Placement::Right.split((960, 320));
+        assert_eq!(game, Rect::new(0, 0, 640, 320));
+        assert_eq!(keypad, Rect::new(640, 0, 320, 320));
+
+        let (game, keypad) = Placement::Bottom.split((640, 640));
+        assert_eq!(game, Rect::new(0, 0, 640, 320));
+        assert_eq!(keypad, Rect::new(160, 320, 320, 320));
+
+        assert_eq!(Placement::Right.window_size((640, 320)), (960, 320));
+        assert_eq!(Placement::Bottom.window_size((640, 320)), (640, 640));
+    }
+
+    #[test]
+    fn keys_under_pointer() {
+        let area = Rect::new(640, 0, 320, 320);
+
+        assert_eq!(key_at(area, 641, 1), Some(0x1));
+        assert_eq!(key_at(area, 959, 0), Some(0xC));
+        assert_eq!(key_at(area, 720, 319), Some(0x0));
+        assert_eq!(key_at(area, 639, 10), None);
+        assert_eq!(key_at(area, 700, 320), None);
+    }
+}
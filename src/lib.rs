@@ -0,0 +1,11 @@
+//! Core of the Chirp-8 emulator: the machine state with its reference interpreter, a threaded
+//! code execution engine, an ahead-of-time translator, a disassembler and the CDP1802 used for
+//! machine code subroutines. The SDL frontend lives in the binary.
+
+pub mod aot;
+pub mod cdp1802;
+pub mod disasm;
+pub mod instruction;
+pub mod machine;
+pub mod memory;
+pub mod threaded;
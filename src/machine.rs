@@ -0,0 +1,520 @@
+use color_eyre::{
+    eyre::{bail, Context as _},
+    Result,
+};
+use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+use crate::{
+    cdp1802::Cdp1802,
+    instruction::{Instruction, IntExt as _},
+    memory::{Memory, RegIdx, Registers},
+};
+
+pub const WIDTH: u8 = 64;
+pub const HEIGHT: u8 = 32;
+
+/// Display contents, indexed by `[x][y]`
+pub type Framebuffer = [[bool; HEIGHT as usize]; WIDTH as usize];
+
+/// Behaviors that differ between CHIP-8 implementations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6 & 8XYE shift VY instead of VX
+    pub old_shift: bool,
+    /// BNNN jumps to XNN + VX instead of NNN + V0
+    pub new_jump: bool,
+    /// FX55 & FX65 increment the index register
+    pub old_store_load: bool,
+    /// FX1E sets VF when index overflows 0x1000
+    pub index_overflow: bool,
+}
+
+/// The sound timer started or ran out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoundEvent {
+    /// Emulated time of the change, see [`Machine::cycles`]
+    pub cycle: u64,
+    pub on: bool,
+}
+
+/// Progress of an `FX0A` waiting for a key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum KeyWait {
+    /// No `FX0A` is executing
+    #[default]
+    Idle,
+    /// Waiting for a key to go down, keys held before the wait started don't count
+    Press,
+    /// The key went down, waiting for it to go up again
+    Release(u8),
+}
+
+/// The complete CHIP-8 machine state, independent of any frontend.
+///
+/// This is the reference interpreter, other execution engines must behave exactly like it.
+#[derive(Debug)]
+pub struct Machine {
+    pub quirks: Quirks,
+
+    pub memory: Memory,
+    pub cpu: Cdp1802,
+    pub framebuffer: Framebuffer,
+    pub stack: Vec<u16>,
+    /// Held keys, only changed through [`Machine::set_key`] so presses and releases are seen
+    keys: [bool; 16],
+    /// Keys that went down or up since the last `FX0A` looked, one bit per key
+    presses: u16,
+    releases: u16,
+    key_wait: KeyWait,
+
+    pub regs: Registers,
+    pub index: u16,
+
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+
+    pub pc: u16,
+
+    /// Instructions executed so far, the emulated clock. Execution engines that don't go
+    /// through [`Machine::step`] have to advance it themselves.
+    pub cycles: u64,
+    /// Sound timer changes not taken by the frontend yet
+    sound_events: Vec<SoundEvent>,
+
+    rng: StdRng,
+    redraw: bool,
+}
+
+impl Machine {
+    pub fn new(rom: &[u8], quirks: Quirks) -> Self {
+        Self {
+            quirks,
+
+            memory: Memory::new(rom),
+            cpu: Cdp1802::new(),
+            framebuffer: [[false; HEIGHT as usize]; WIDTH as usize],
+            stack: Vec::new(),
+            keys: [false; 16],
+            presses: 0,
+            releases: 0,
+            key_wait: KeyWait::Idle,
+
+            regs: Registers::new(),
+            index: 0,
+
+            delay_timer: 0,
+            sound_timer: 0,
+
+            pc: Memory::ROM_OFFSET,
+
+            cycles: 0,
+            sound_events: Vec::new(),
+
+            rng: StdRng::from_rng(&mut rand::rng()),
+            redraw: true,
+        }
+    }
+
+    /// Makes `CXNN` produce the same sequence of numbers on every run with the same seed.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Returns whether the framebuffer changed since the last call.
+    pub fn take_redraw(&mut self) -> bool {
+        std::mem::take(&mut self.redraw)
+    }
+
+    /// Returns when the sound timer started and stopped since the last call, in order.
+    pub fn take_sound_events(&mut self) -> Vec<SoundEvent> {
+        std::mem::take(&mut self.sound_events)
+    }
+
+    pub fn keys(&self) -> &[bool; 16] {
+        &self.keys
+    }
+
+    /// Presses or releases a keypad key. Every change is remembered until an `FX0A` sees it,
+    /// so a key pressed and released between two instructions still counts.
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let key = key as usize & 0xF;
+        if self.keys[key] == pressed {
+            return;
+        }
+
+        self.keys[key] = pressed;
+        match pressed {
+            true => self.presses |= 1 << key,
+            false => self.releases |= 1 << key,
+        }
+    }
+
+    /// Decrements both timers by `ticks` (one tick is 1/60th of a second on real hardware).
+    pub fn tick_timers(&mut self, ticks: u8) {
+        self.delay_timer = self.delay_timer.saturating_sub(ticks);
+        self.set_sound_timer(self.sound_timer.saturating_sub(ticks));
+    }
+
+    /// Sets the sound timer, recording a [`SoundEvent`] if the beeper starts or stops.
+    pub fn set_sound_timer(&mut self, value: u8) {
+        if (self.sound_timer > 0) != (value > 0) {
+            self.sound_events.push(SoundEvent {
+                cycle: self.cycles,
+                on: value > 0,
+            });
+        }
+
+        self.sound_timer = value;
+    }
+
+    /// Fetches, decodes and executes one instruction.
+    pub fn step(&mut self) -> Result<()> {
+        let inst = self.fetch_and_decode()?;
+        self.cycles += 1;
+
+        self.execute(inst)
+    }
+
+    pub fn fetch_and_decode(&mut self) -> Result<Instruction> {
+        let pc = self.pc;
+        self.pc += 2;
+
+        match self.memory.read_instruction(pc)? {
+            Some(inst) => Ok(inst),
+            None => bail!("unknown instruction: {:04x}", self.memory.read_u16(pc)?),
+        }
+    }
+
+    /// Executes an already fetched instruction, `pc` must point past it.
+    pub fn execute(&mut self, inst: Instruction) -> Result<()> {
+        match inst {
+            Instruction::MachineCall(addr) => self.machine_call(addr)?,
+            Instruction::ClearScreen => {
+                self.framebuffer = [[false; HEIGHT as usize]; WIDTH as usize];
+                self.redraw = true;
+            }
+            Instruction::Jump(nnn) => self.pc = nnn,
+            Instruction::SetIm(vx, nn) => self.regs[vx] = nn,
+            Instruction::AddIm(vx, nn) => self.regs[vx] = self.regs[vx].wrapping_add(nn),
+            Instruction::SetIndex(nnn) => self.index = nnn,
+            Instruction::Draw(vx, vy, n) => self.draw(vx, vy, n)?,
+            Instruction::Call(addr) => {
+                self.stack.push(self.pc);
+                self.pc = addr;
+            }
+            Instruction::Return => match self.stack.pop() {
+                Some(pc) => self.pc = pc,
+                None => bail!("invalid return; call stack is empty"),
+            },
+            Instruction::SkipEqIm(vx, nn) => self.skip_if(|s| s.regs[vx] == nn),
+            Instruction::SkipNeIm(vx, nn) => self.skip_if(|s| s.regs[vx] != nn),
+            Instruction::SkipEq(vx, vy) => {
+                self.skip_if(|s| s.regs[vx] == s.regs[vy]);
+            }
+            Instruction::SkipNe(vx, vy) => {
+                self.skip_if(|s| s.regs[vx] != s.regs[vy]);
+            }
+            Instruction::Set(vx, vy) => self.regs[vx] = self.regs[vy],
+            Instruction::Or(vx, vy) => self.regs[vx] |= self.regs[vy],
+            Instruction::And(vx, vy) => self.regs[vx] &= self.regs[vy],
+            Instruction::Xor(vx, vy) => self.regs[vx] ^= self.regs[vy],
+            Instruction::Add(vx, vy) => {
+                let (result, overflow) = self.regs[vx].overflowing_add(self.regs[vy]);
+
+                (self.regs[vx], self.regs[RegIdx::FLAG]) = (result, overflow as u8);
+            }
+            Instruction::Sub(vx, vy) => {
+                let (x, y) = (self.regs[vx], self.regs[vy]);
+
+                self.regs[vx] = x.wrapping_sub(y);
+                self.regs[RegIdx::FLAG] = (x >= y) as u8;
+            }
+            Instruction::SubOpp(vx, vy) => {
+                let (x, y) = (self.regs[vx], self.regs[vy]);
+
+                self.regs[vx] = y.wrapping_sub(x);
+                self.regs[RegIdx::FLAG] = (y >= x) as u8;
+            }
+            Instruction::Shr(vx, vy) => {
+                if self.quirks.old_shift {
+                    self.regs[vx] = self.regs[vy];
+                }
+
+                self.regs[RegIdx::FLAG] = self.regs[vx] & 1;
+                self.regs[vx] >>= 1;
+            }
+            Instruction::Shl(vx, vy) => {
+                if self.quirks.old_shift {
+                    self.regs[vx] = self.regs[vy];
+                }
+
+                self.regs[RegIdx::FLAG] = (self.regs[vx] & 0b10000000) >> 7;
+                self.regs[vx] <<= 1;
+            }
+            Instruction::JumpV0(nnn) => {
+                let reg = RegIdx::new(if self.quirks.new_jump {
+                    nnn.nibble2()
+                } else {
+                    0
+                });
+
+                self.pc = self.regs[reg] as u16 + nnn;
+            }
+            Instruction::RandAnd(vx, nn) => self.regs[vx] = self.rng.random::<u8>() & nn,
+            Instruction::GetDelay(vx) => self.regs[vx] = self.delay_timer,
+            Instruction::SetDelay(vx) => self.delay_timer = self.regs[vx],
+            Instruction::SetSound(vx) => self.set_sound_timer(self.regs[vx]),
+            Instruction::AddIndex(vx) => {
+                self.index += self.regs[vx] as u16;
+                if self.quirks.index_overflow && self.index >= 4096 {
+                    self.regs[RegIdx::FLAG] = 1;
+                }
+            }
+            Instruction::SetBcd(vx) => {
+                let x = self.regs[vx];
+
+                self.memory.write_u8(self.index, x / 100)?;
+                self.memory.write_u8(self.index + 1, (x / 10) % 10)?;
+                self.memory.write_u8(self.index + 2, x % 10)?;
+            }
+            Instruction::RegStore(vx) => {
+                for idx in 0..=vx.as_u8() {
+                    self.memory
+                        .write_u8(self.index + idx as u16, self.regs[RegIdx::new(idx)])?;
+                }
+
+                if self.quirks.old_store_load {
+                    self.index += vx.as_u8() as u16 + 1;
+                }
+            }
+            Instruction::RegLoad(vx) => {
+                for i in 0..=vx.as_u8() {
+                    self.regs[RegIdx::new(i)] = self.memory.read_u8(self.index + i as u16)?;
+                }
+
+                if self.quirks.old_store_load {
+                    self.index += vx.as_u8() as u16 + 1;
+                }
+            }
+            Instruction::IndexCharacter(vx) => {
+                let x = self.regs[vx];
+
+                self.index = Memory::FONT_OFFSET + 5 * (x as u16 & 0x0F);
+            }
+            Instruction::GetKey(vx) => {
+                // like the VIP, wait for a key to be pressed and released again
+                if self.key_wait == KeyWait::Idle {
+                    self.presses = 0;
+                    self.releases = 0;
+                    self.key_wait = KeyWait::Press;
+                }
+
+                if self.key_wait == KeyWait::Press && self.presses != 0 {
+                    self.key_wait = KeyWait::Release(self.presses.trailing_zeros() as u8);
+                }
+
+                match self.key_wait {
+                    KeyWait::Release(key) if self.releases & 1 << key != 0 => {
+                        self.regs[vx] = key;
+                        self.key_wait = KeyWait::Idle;
+                    }
+                    _ => self.pc -= 2, // do this instruction again
+                }
+            }
+            Instruction::SkipKeyEq(vx) => {
+                if self.keys[self.regs[vx] as usize] {
+                    self.pc += 2;
+                }
+            }
+            Instruction::SkipKeyNe(vx) => {
+                if !self.keys[self.regs[vx] as usize] {
+                    self.pc += 2;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn skip_if(&mut self, pred: impl Fn(&Self) -> bool) {
+        if pred(self) {
+            self.pc += 2
+        }
+    }
+
+    /// Runs a 1802 subroutine, V0 - VF and I are passed the way the VIP interpreter does it.
+    fn machine_call(&mut self, addr: u16) -> Result<()> {
+        for idx in 0..16 {
+            self.memory
+                .write_u8(Cdp1802::VARIABLES + idx as u16, self.regs[RegIdx::new(idx)])?;
+        }
+        self.cpu.r[Cdp1802::INDEX_REG] = self.index;
+        self.cpu.r[Cdp1802::PC_REG] = self.pc;
+
+        self.cpu
+            .call(&mut self.memory, addr)
+            .wrap_err_with(|| format!("failed to execute machine code at {addr:03x}"))?;
+
+        for idx in 0..16 {
+            self.regs[RegIdx::new(idx)] = self.memory.read_u8(Cdp1802::VARIABLES + idx as u16)?;
+        }
+        self.index = self.cpu.r[Cdp1802::INDEX_REG] & 0x0FFF;
+
+        Ok(())
+    }
+
+    /// `DXYN`, XORs the `n` bytes of sprite at the index register onto the screen at
+    /// (VX, VY) and sets VF if that turned a pixel off.
+    pub fn draw(&mut self, vx: RegIdx, vy: RegIdx, n: u8) -> Result<()> {
+        let (x, y) = (self.regs[vx] % WIDTH, self.regs[vy] % HEIGHT);
+        self.regs[RegIdx::FLAG] = 0;
+
+        for i in 0..n {
+            let pixel = self.memory.read_u8(self.index + i as u16)?;
+            for j in 0..8 {
+                if (pixel & (0x80 >> j)) != 0 {
+                    if x + j >= WIDTH || y + i >= HEIGHT {
+                        continue;
+                    }
+
+                    if self.framebuffer[(x + j) as usize][(y + i) as usize] {
+                        self.regs[RegIdx::FLAG] = 1
+                    }
+
+                    self.framebuffer[(x + j) as usize][(y + i) as usize] ^= true;
+                }
+            }
+        }
+
+        self.redraw = true;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `F50A` followed by a jump to itself
+    fn get_key() -> Machine {
+        Machine::new(&[0xF5, 0x0A, 0x12, 0x02], Quirks::default())
+    }
+
+    #[test]
+    fn get_key_waits_for_release() {
+        let mut machine = get_key();
+
+        machine.step().unwrap();
+        machine.set_key(7, true);
+        machine.step().unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.pc, Memory::ROM_OFFSET, "still held");
+
+        machine.set_key(7, false);
+        machine.step().unwrap();
+        assert_eq!(machine.pc, Memory::ROM_OFFSET + 2);
+        assert_eq!(machine.regs[RegIdx::new(5)], 7);
+    }
+
+    #[test]
+    fn get_key_ignores_held_keys() {
+        let mut machine = get_key();
+        machine.set_key(3, true);
+
+        machine.step().unwrap();
+        machine.set_key(3, false);
+        machine.step().unwrap();
+        assert_eq!(machine.pc, Memory::ROM_OFFSET, "pressed before the wait");
+
+        machine.set_key(3, true);
+        machine.set_key(3, false);
+        machine.step().unwrap();
+        assert_eq!(machine.pc, Memory::ROM_OFFSET + 2);
+        assert_eq!(machine.regs[RegIdx::new(5)], 3);
+    }
+
+    #[test]
+    fn calls_into_zeroed_memory_fail() {
+        // SYS 0x300, nothing is loaded there
+        let mut machine = Machine::new(&[0x03, 0x00], Quirks::default());
+
+        assert!(machine.step().is_err());
+    }
+
+    #[test]
+    fn sound_events() {
+        // V0 = 2, sound timer = V0, jump to itself
+        let mut machine = Machine::new(&[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04], Quirks::default());
+
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        machine.tick_timers(1);
+        machine.step().unwrap();
+        machine.tick_timers(1);
+        machine.tick_timers(1);
+
+        assert_eq!(
+            machine.take_sound_events(),
+            [
+                SoundEvent { cycle: 2, on: true },
+                SoundEvent {
+                    cycle: 4,
+                    on: false
+                }
+            ]
+        );
+        assert!(machine.take_sound_events().is_empty());
+    }
+
+    #[test]
+    fn chained_get_keys() {
+        let mut machine = Machine::new(&[0xF0, 0x0A, 0xF1, 0x0A], Quirks::default());
+
+        machine.step().unwrap();
+        machine.set_key(2, true);
+        machine.set_key(2, false);
+        machine.step().unwrap();
+        machine.set_key(2, true);
+
+        // the second wait doesn't take the key that is still held
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.pc, Memory::ROM_OFFSET + 2);
+    }
+
+    #[test]
+    fn watchpoints_see_data_accesses() {
+        use crate::memory::{Access, Watchpoint};
+
+        // I = 300, BCD of V0 at I, draw a sprite from I
+        let mut machine = Machine::new(&[0xA3, 0x00, 0xF0, 0x33, 0xD0, 0x03], Quirks::default());
+        machine.memory.watchpoints.push(Watchpoint {
+            start: 0x301,
+            end: 0x302,
+            read: true,
+            write: true,
+        });
+
+        machine.step().unwrap();
+        assert_eq!(machine.memory.take_hit(), None);
+        machine.step().unwrap();
+        assert_eq!(
+            machine.memory.take_hit(),
+            Some(Access {
+                addr: 0x301,
+                write: true
+            })
+        );
+        machine.step().unwrap();
+        assert_eq!(
+            machine.memory.take_hit(),
+            Some(Access {
+                addr: 0x301,
+                write: false
+            })
+        );
+    }
+}
@@ -1,37 +1,103 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use color_eyre::{
     eyre::{eyre, Context as _},
     Result, Section as _,
 };
+#[cfg(feature = "window")]
+use display::Persistence;
+#[cfg(feature = "window")]
 use emulator::Chip8;
+#[cfg(feature = "window")]
+use filter::Filter;
+use keymap::Keymap;
+use palette::{Color, Palette};
+use sound::{Tone, Waveform};
 
+use chirp_8::{instruction::Syntax, machine::Quirks};
+
+#[cfg(feature = "window")]
+mod condition;
+#[cfg(feature = "window")]
+mod debug_view;
+#[cfg(feature = "window")]
+mod debugger;
+#[cfg(feature = "window")]
 mod display;
+#[cfg(feature = "window")]
 mod emulator;
-mod instruction;
-mod memory;
+mod filter;
+#[cfg(feature = "window")]
+mod font;
+#[cfg(feature = "window")]
+mod gamepad;
+#[cfg(feature = "window")]
+mod gdb;
+#[cfg(feature = "window")]
+mod geometry;
+mod headless;
+mod keymap;
+#[cfg(feature = "window")]
+mod keypad;
+mod palette;
+mod recorder;
+#[cfg(feature = "window")]
+mod run_control;
+mod screenshot;
 mod sound;
+#[cfg(feature = "window")]
+mod speaker;
+mod tui;
 
 fn main() -> Result<()> {
     color_eyre::install()?;
 
     let args = Args::parse();
 
+    if let Some(command) = args.command {
+        return command.run();
+    }
+
     let rom = std::fs::read(&args.rom).with_suggestion(|| "check if the ROM file exists")?;
 
+    if args.headless {
+        return headless::run(&rom, &args);
+    }
+    if args.tui {
+        return tui::run(&rom, &args);
+    }
+
+    window(&rom, args)
+}
+
+#[cfg(feature = "window")]
+fn window(rom: &[u8], args: Args) -> Result<()> {
     let context = sdl2::init().map_err(|_| eyre!("failed to initialize sdl2"))?;
 
-    let emu = Chip8::new(&rom, context, args).with_context(|| "failed to create emulator")?;
+    let emu = Chip8::new(rom, context, args).with_context(|| "failed to create emulator")?;
     emu.run()
         .with_context(|| "an error occured during emulating")?;
 
     Ok(())
 }
 
+#[cfg(not(feature = "window"))]
+fn window(_: &[u8], _: Args) -> Result<()> {
+    Err(eyre!(
+        "built without the window, run with --tui or --headless"
+    ))
+}
+
+/// Scale used when neither `--scale` nor a remembered window geometry is available
+pub const DEFAULT_SCALE: u32 = 10;
+
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 /// A simple CHIP-8 emulator
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
     /// # of instructions per second that emulator will execute
     #[arg(short, long, default_value_t = 700)]
     pub ips: u64,
@@ -50,13 +116,204 @@ pub struct Args {
     /// Sound/delay timer perioid in milliseconds
     #[arg(short, long, default_value_t = 16)]
     pub timer_period: u128,
-    /// Scale for the display, the size is determined by (64 * scale) x (32 * scale)
-    #[arg(short = 'c', long, default_value_t = 10)]
-    pub scale: u32,
+    /// Initial scale of the window, (64 * scale) x (32 * scale). Defaults to the window
+    /// geometry of the last run, or 10
+    #[arg(short = 'c', long)]
+    pub scale: Option<u32>,
+    #[cfg(feature = "window")]
+    /// Show a clickable COSMAC VIP keypad next to the game
+    #[arg(long, value_enum)]
+    pub keypad: Option<keypad::Placement>,
+    #[cfg(feature = "window")]
+    /// Only scale the image by whole numbers when resizing the window
+    #[arg(long, default_value_t = false)]
+    pub integer_scaling: bool,
+    /// Color theme, either a built-in one (default, amber, green, lcd, octo) or a theme file
+    #[arg(long, default_value = "default")]
+    pub theme: String,
+    /// Foreground color as #RRGGBB, overrides the theme
+    #[arg(long, value_parser = palette::parse_color)]
+    pub fg: Option<Color>,
+    /// Background color as #RRGGBB, overrides the theme
+    #[arg(long, value_parser = palette::parse_color)]
+    pub bg: Option<Color>,
+    #[cfg(feature = "window")]
+    /// Show pixels that were turned off for a while longer, reducing sprite flicker
+    #[arg(long, value_enum, default_value_t = Persistence::None)]
+    pub persistence: Persistence,
+    #[cfg(feature = "window")]
+    /// Number of refreshes it takes a pixel to fade out with `--persistence decay`
+    #[arg(long, default_value_t = 6)]
+    pub decay_frames: u32,
+    /// Keyboard layout, either a preset (qwerty, azerty, qwertz, dvorak, numpad, hex) or a
+    /// keymap file
+    #[arg(long, default_value = "qwerty")]
+    pub keymap: String,
+    /// Bind keys to a keypad key on top of the keymap, e.g. `--bind "5 = Up KP8"`
+    #[arg(long, value_name = "BINDING")]
+    pub bind: Vec<String>,
+    #[cfg(feature = "window")]
+    /// Gamepad mapping file, by default `<rom>.gamepad` is used if it exists
+    #[arg(long)]
+    pub gamepad: Option<PathBuf>,
+    #[cfg(feature = "window")]
+    /// Post-processing filter, F2 cycles through them while running
+    #[arg(long, value_enum, default_value_t = Filter::None)]
+    pub filter: Filter,
     /// Volume (0 - 100), higher values will be identical to 100
     #[arg(short, long, default_value_t = 50)]
     pub volume: u8,
+    /// Run without a window or sound device for `--frames` frames
+    #[arg(long, requires = "frames")]
+    pub headless: bool,
+    /// Number of frames (1/60 s of emulated time) to run in headless mode
+    #[arg(long)]
+    pub frames: Option<u64>,
+    /// Run on the threaded engine in headless mode, which compiles runs of instructions into
+    /// closures. Frames can end a few instructions late
+    #[arg(long, requires = "headless")]
+    pub threaded: bool,
+    /// Save a screenshot of the last frame in headless mode, as PBM if the extension is .pbm
+    /// and PNG otherwise
+    #[arg(long, requires = "headless")]
+    pub screenshot: Option<PathBuf>,
+    /// Take the screenshot after this many frames instead of the last one. Repeat it for
+    /// several screenshots, which get the frame number appended to their name
+    #[arg(long, value_name = "FRAME", requires = "screenshot")]
+    pub screenshot_at: Vec<u64>,
+    #[cfg(feature = "window")]
+    /// Start paused with a debugger reading commands from the terminal
+    #[arg(long, conflicts_with_all = ["headless", "tui"])]
+    pub debug: bool,
+    #[cfg(feature = "window")]
+    /// Open a window showing registers, stack, disassembly and memory
+    #[arg(long, conflicts_with_all = ["headless", "tui"])]
+    pub debug_view: bool,
+    #[cfg(feature = "window")]
+    /// Wait for GDB to connect on this local TCP port, then let it control the machine
+    #[arg(long, value_name = "PORT", conflicts_with_all = ["headless", "tui", "debug", "debug_view"])]
+    pub gdb: Option<u16>,
+    /// Run in the terminal instead of a window, Esc quits
+    #[arg(long, default_value_t = false, conflicts_with = "headless")]
+    pub tui: bool,
+    /// How the terminal frontend draws the screen
+    #[arg(long, value_enum, default_value_t = tui::Graphics::HalfBlock)]
+    pub tui_graphics: tui::Graphics,
+    /// Record from the start into an animated GIF if the path ends in .gif, otherwise into a
+    /// directory of numbered PNGs. F9 starts and stops recording while running
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+    /// Also record the sound into a WAV file next to the recording
+    #[arg(long, default_value_t = false)]
+    pub record_audio: bool,
+    /// Write the sound into a WAV file in headless mode, timed by emulated time
+    #[arg(long, requires = "headless", conflicts_with = "record_audio")]
+    pub wav: Option<PathBuf>,
+    #[cfg(feature = "window")]
+    /// How to play sound, without sound if the device can't be opened
+    #[arg(long, value_enum, default_value_t = speaker::Backend::Rodio)]
+    pub audio: speaker::Backend,
+    /// Frequency of the beeper in Hz
+    #[arg(long, default_value_t = 440.0)]
+    pub tone: f32,
+    /// Shape of the beeper's tone
+    #[arg(long, value_enum, default_value_t = Waveform::Saw)]
+    pub waveform: Waveform,
+    /// Milliseconds the beeper takes to fade in
+    #[arg(long, default_value_t = 5)]
+    pub attack: u64,
+    /// Milliseconds the beeper takes to fade out
+    #[arg(long, default_value_t = 20)]
+    pub release: u64,
     /// Path to the ROM for emulator to run
     #[arg(default_value_os_t = PathBuf::from("rom.ch8"))]
     pub rom: PathBuf,
 }
+
+impl Args {
+    pub fn quirks(&self) -> Quirks {
+        Quirks {
+            old_shift: self.old_shift_behavior,
+            new_jump: self.new_jump_behavior,
+            old_store_load: self.old_store_load_behavior,
+            index_overflow: self.index_overflow,
+        }
+    }
+
+    /// The theme with the color overrides applied
+    pub fn palette(&self) -> Result<Palette> {
+        let mut palette = Palette::load(&self.theme)?;
+        if let Some(bg) = self.bg {
+            palette.0[0] = bg;
+        }
+        if let Some(fg) = self.fg {
+            palette.0[1] = fg;
+        }
+
+        Ok(palette)
+    }
+
+    pub fn tone(&self) -> Tone {
+        Tone {
+            frequency: self.tone,
+            waveform: self.waveform,
+            volume: self.volume.min(100) as f32 / 100.0,
+            attack: Duration::from_millis(self.attack),
+            release: Duration::from_millis(self.release),
+        }
+    }
+
+    /// The keymap with the bindings applied
+    pub fn keymap(&self) -> Result<Keymap> {
+        let mut keymap = Keymap::load(&self.keymap)?;
+        for binding in &self.bind {
+            keymap
+                .bind(binding)
+                .wrap_err_with(|| format!("invalid binding `{binding}`"))?;
+        }
+
+        Ok(keymap)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Translate a ROM ahead of time into a Rust module running it natively
+    Aot {
+        /// Path to the ROM to translate
+        rom: PathBuf,
+        /// Where to write the generated Rust module
+        #[arg(short, long, default_value_os_t = PathBuf::from("game.rs"))]
+        output: PathBuf,
+    },
+    /// Print a listing of a ROM with labels for jump and call targets
+    Disasm {
+        /// Path to the ROM to disassemble
+        rom: PathBuf,
+        /// Assembly syntax of the listing
+        #[arg(short, long, value_enum, default_value_t = Syntax::Cowgod)]
+        syntax: Syntax,
+    },
+}
+
+impl Command {
+    fn run(self) -> Result<()> {
+        match self {
+            Command::Aot { rom, output } => {
+                let data =
+                    std::fs::read(&rom).with_suggestion(|| "check if the ROM file exists")?;
+                let name = rom.file_name().unwrap_or(rom.as_os_str()).to_string_lossy();
+
+                std::fs::write(&output, chirp_8::aot::translate(&data, &name))
+                    .with_context(|| format!("failed to write {}", output.display()))
+            }
+            Command::Disasm { rom, syntax } => {
+                let data =
+                    std::fs::read(&rom).with_suggestion(|| "check if the ROM file exists")?;
+
+                print!("{}", chirp_8::disasm::disassemble(&data, syntax));
+                Ok(())
+            }
+        }
+    }
+}
@@ -3,12 +3,61 @@ use color_eyre::{
     Result,
 };
 
+use std::cell::Cell;
+
+use crate::instruction::Instruction;
+
+/// Reports accesses to the addresses `start..=end` through [`Memory::take_hit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+}
+
+/// An access to a watched address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub addr: u16,
+    pub write: bool,
+}
+
 #[derive(Debug)]
-pub struct Memory([u8; 4 * 1024]);
+pub struct Memory {
+    bytes: [u8; 4 * 1024],
+    /// Decoded instruction at each address, `None` if it wasn't decoded since the last write.
+    /// Undecodable words are cached too, as `Some(None)`.
+    decoded: Box<[Option<Option<Instruction>>]>,
+    /// Lowest and highest address written since the last [`Memory::take_written`]
+    written: Option<(u16, u16)>,
+    pub watchpoints: Vec<Watchpoint>,
+    /// First watched access since the last [`Memory::take_hit`], reads only borrow memory
+    hit: Cell<Option<Access>>,
+}
 
 impl Memory {
     pub const FONT_OFFSET: u16 = 0x50;
     pub const ROM_OFFSET: u16 = 512;
+    /// Sprites of the hex digits, 5 bytes each
+    pub const FONT: [u8; 80] = [
+        0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+        0x20, 0x60, 0x20, 0x20, 0x70, // 1
+        0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+        0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+        0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+        0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+        0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+        0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+        0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+        0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+        0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+        0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+        0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+        0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+        0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+        0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+    ];
 
     pub fn new(rom: &[u8]) -> Self {
         // 4 KiB
@@ -16,47 +65,53 @@ impl Memory {
 
         // font data
         // 0x50 appears to be a popular place to put it
-        memory[Self::FONT_OFFSET as usize..(Self::FONT_OFFSET + 0x50) as usize].copy_from_slice(&[
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ]);
+        memory[Self::FONT_OFFSET as usize..Self::FONT_OFFSET as usize + Self::FONT.len()]
+            .copy_from_slice(&Self::FONT);
 
         // apparently some roms expect to be place at offset 512 in memory
         memory[Self::ROM_OFFSET as usize..Self::ROM_OFFSET as usize + rom.len()]
             .copy_from_slice(rom);
 
-        Self(memory)
+        Self {
+            bytes: memory,
+            ..Default::default()
+        }
     }
 
+    /// Reads a byte, instructions are fetched without it so they don't trigger watchpoints.
     pub fn read_u8(&self, pos: u16) -> Result<u8> {
-        self.0
+        self.watch(pos, false);
+
+        self.get(pos)
+    }
+
+    fn get(&self, pos: u16) -> Result<u8> {
+        self.bytes
             .get(pos as usize)
-            .ok_or_else(|| eyre!("tried to read out of bounds: {pos} > {}", self.0.len()))
+            .ok_or_else(|| eyre!("tried to read out of bounds: {pos} > {}", self.bytes.len()))
             .copied()
     }
 
     pub fn write_u8(&mut self, pos: u16, byte: u8) -> Result<()> {
+        self.watch(pos, true);
         let pos = pos as usize;
 
-        if pos > self.0.len() {
-            bail!("tried to write out of bounds: {pos} > {}", self.0.len())
+        if pos >= self.bytes.len() {
+            bail!("tried to write out of bounds: {pos} > {}", self.bytes.len())
+        }
+
+        self.bytes[pos] = byte;
+        self.written = Some(match self.written {
+            Some((lo, hi)) => (lo.min(pos as u16), hi.max(pos as u16)),
+            None => (pos as u16, pos as u16),
+        });
+
+        // the byte is part of the instructions starting at `pos` and `pos - 1`
+        self.decoded[pos] = None;
+        if let Some(prev) = pos.checked_sub(1) {
+            self.decoded[prev] = None;
         }
 
-        self.0[pos] = byte;
         Ok(())
     }
 
@@ -66,15 +121,77 @@ impl Memory {
 
         Ok((byte1 << 8) | byte2)
     }
+
+    /// Reads and decodes the instruction at `pos`, reusing the previous result if the memory
+    /// there hasn't been written to since.
+    pub fn read_instruction(&mut self, pos: u16) -> Result<Option<Instruction>> {
+        if let Some(&Some(inst)) = self.decoded.get(pos as usize) {
+            return Ok(inst);
+        }
+
+        let word = (self.get(pos)? as u16) << 8 | self.get(pos + 1)? as u16;
+        let inst = Instruction::decode(word);
+        self.decoded[pos as usize] = Some(inst);
+
+        Ok(inst)
+    }
+
+    /// Returns the first access to an address in [`Memory::watchpoints`] since the last call.
+    pub fn take_hit(&mut self) -> Option<Access> {
+        self.hit.take()
+    }
+
+    fn watch(&self, addr: u16, write: bool) {
+        if self.watchpoints.is_empty() || self.hit.get().is_some() {
+            return;
+        }
+
+        let watched = self.watchpoints.iter().any(|watch| {
+            (watch.start..=watch.end).contains(&addr)
+                && if write { watch.write } else { watch.read }
+        });
+        if watched {
+            self.hit.set(Some(Access { addr, write }));
+        }
+    }
+
+    /// Returns the inclusive range of addresses written since the last call, if any.
+    pub fn take_written(&mut self) -> Option<(u16, u16)> {
+        self.written.take()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Reads a byte without triggering watchpoints, for debuggers looking at memory.
+    pub fn peek(&self, addr: u16) -> Option<u8> {
+        self.bytes.get(addr as usize).copied()
+    }
+
+    /// Up to `len` bytes starting at `addr`, cut short at the end of memory, see
+    /// [`Memory::peek`].
+    pub fn peek_slice(&self, addr: u16, len: u16) -> &[u8] {
+        let start = (addr as usize).min(self.bytes.len());
+        let end = (start + len as usize).min(self.bytes.len());
+
+        &self.bytes[start..end]
+    }
 }
 
 impl Default for Memory {
     fn default() -> Self {
-        Self([0; 4 * 1024])
+        Self {
+            bytes: [0; 4 * 1024],
+            decoded: vec![None; 4 * 1024].into_boxed_slice(),
+            written: None,
+            watchpoints: Vec::new(),
+            hit: Cell::new(None),
+        }
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 #[repr(transparent)]
 pub struct Registers([u8; 16]);
 
@@ -118,8 +235,108 @@ impl RegIdx {
     }
 }
 
+/// Written with a hex digit like the register dumps and Cowgod's reference, `VA` rather
+/// than `V10`
 impl std::fmt::Display for RegIdx {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "V{}", self.0)
+        write!(f, "V{:X}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_names_are_hex() {
+        assert_eq!(RegIdx::new(9).to_string(), "V9");
+        assert_eq!(RegIdx::new(10).to_string(), "VA");
+        assert_eq!(RegIdx::FLAG.to_string(), "VF");
+    }
+
+    #[test]
+    fn cached_instruction() {
+        let mut memory = Memory::new(&[0x61, 0xFF]);
+
+        assert_eq!(
+            memory.read_instruction(Memory::ROM_OFFSET).unwrap(),
+            Some(Instruction::SetIm(RegIdx::new(1), 0xFF))
+        );
+        assert_eq!(
+            memory.read_instruction(Memory::ROM_OFFSET).unwrap(),
+            Some(Instruction::SetIm(RegIdx::new(1), 0xFF))
+        );
+    }
+
+    #[test]
+    fn write_invalidates_cache() {
+        let mut memory = Memory::new(&[0x61, 0xFF, 0x00, 0xE0]);
+
+        memory.read_instruction(Memory::ROM_OFFSET).unwrap();
+        memory.read_instruction(Memory::ROM_OFFSET + 1).unwrap();
+        memory.read_instruction(Memory::ROM_OFFSET + 2).unwrap();
+
+        memory.write_u8(Memory::ROM_OFFSET + 1, 0x10).unwrap();
+
+        assert_eq!(
+            memory.read_instruction(Memory::ROM_OFFSET).unwrap(),
+            Some(Instruction::SetIm(RegIdx::new(1), 0x10))
+        );
+        assert_eq!(
+            memory.read_instruction(Memory::ROM_OFFSET + 1).unwrap(),
+            Some(Instruction::Jump(0x000))
+        );
+        assert_eq!(
+            memory.read_instruction(Memory::ROM_OFFSET + 2).unwrap(),
+            Some(Instruction::ClearScreen)
+        );
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut memory = Memory::new(&[0x61, 0xFF]);
+        memory.watchpoints.push(Watchpoint {
+            start: 0x300,
+            end: 0x303,
+            read: false,
+            write: true,
+        });
+
+        memory.read_u8(0x300).unwrap();
+        memory.write_u8(0x304, 1).unwrap();
+        assert_eq!(memory.take_hit(), None);
+
+        memory.write_u8(0x302, 1).unwrap();
+        memory.write_u8(0x303, 1).unwrap();
+        assert_eq!(
+            memory.take_hit(),
+            Some(Access {
+                addr: 0x302,
+                write: true
+            })
+        );
+        assert_eq!(memory.take_hit(), None);
+
+        // fetching instructions doesn't count as reading
+        memory.watchpoints[0] = Watchpoint {
+            start: 0x200,
+            end: 0x201,
+            read: true,
+            write: false,
+        };
+        memory.read_instruction(Memory::ROM_OFFSET).unwrap();
+        assert_eq!(memory.peek(0x201), Some(0xFF));
+        assert_eq!(memory.peek_slice(0x200, 2), [0x61, 0xFF]);
+        assert_eq!(memory.take_hit(), None);
+        memory.read_u8(0x201).unwrap();
+        assert!(memory.take_hit().is_some());
+    }
+
+    #[test]
+    fn undecodable_instruction() {
+        let mut memory = Memory::new(&[0xFF, 0xFF]);
+
+        assert_eq!(memory.read_instruction(Memory::ROM_OFFSET).unwrap(), None);
+        assert!(memory.read_instruction(4095).is_err());
     }
 }
@@ -0,0 +1,184 @@
+use std::path::Path;
+
+use color_eyre::{
+    eyre::{bail, eyre, Context as _},
+    Result,
+};
+
+/// A 24-bit RGB color, frontends convert it into their own color type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+#[cfg(feature = "window")]
+impl From<Color> for sdl2::pixels::Color {
+    fn from(Color { r, g, b }: Color) -> Self {
+        Self::RGB(r, g, b)
+    }
+}
+
+/// Colors used to draw the framebuffer.
+///
+/// Index 0 is the background and index 1 the foreground. XO-CHIP draws with two bitplanes,
+/// index 2 is used for pixels set only in the second plane and index 3 for pixels set in both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette(pub [Color; 4]);
+
+impl Palette {
+    /// Built-in themes, selectable by name with `--theme`
+    pub const THEMES: [(&'static str, Palette); 5] = [
+        (
+            "default",
+            Palette::new(0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555),
+        ),
+        (
+            "amber",
+            Palette::new(0x1A0F00, 0xFFB000, 0xB37B00, 0x664600),
+        ),
+        (
+            "green",
+            Palette::new(0x001A05, 0x33FF66, 0x22B347, 0x116628),
+        ),
+        ("lcd", Palette::new(0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F)),
+        ("octo", Palette::new(0x996600, 0xFFCC00, 0xFF6600, 0x662200)),
+    ];
+
+    pub const fn new(background: u32, foreground: u32, color2: u32, color3: u32) -> Self {
+        Self([rgb(background), rgb(foreground), rgb(color2), rgb(color3)])
+    }
+
+    pub const fn background(&self) -> Color {
+        self.0[0]
+    }
+
+    /// Looks up a built-in theme by name, otherwise loads a theme file from that path.
+    pub fn load(theme: &str) -> Result<Self> {
+        if let Some((_, palette)) = Self::THEMES.iter().find(|(name, _)| *name == theme) {
+            return Ok(*palette);
+        }
+
+        let path = Path::new(theme);
+        let text = std::fs::read_to_string(path).wrap_err_with(|| {
+            let names = Self::THEMES.map(|(name, _)| name).join(", ");
+            format!("{theme} is neither a built-in theme ({names}) nor a readable theme file")
+        })?;
+
+        Self::parse(&text).wrap_err_with(|| format!("invalid theme file {}", path.display()))
+    }
+
+    /// Parses a theme file. Each line is `<key> = <color>` or a comment starting with `#`,
+    /// keys are `background`, `foreground`, `color2` and `color3`. Missing keys keep the
+    /// default theme's colors.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut palette = Self::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| eyre!("line {}: expected `<key> = <color>`", number + 1))?;
+
+            let idx = match key.trim() {
+                "background" => 0,
+                "foreground" => 1,
+                "color2" => 2,
+                "color3" => 3,
+                key => bail!("line {}: unknown key `{key}`", number + 1),
+            };
+
+            palette.0[idx] =
+                parse_color(value.trim()).map_err(|err| eyre!("line {}: {err}", number + 1))?;
+        }
+
+        Ok(palette)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::THEMES[0].1
+    }
+}
+
+/// Parses `#RRGGBB` or `RRGGBB`, used by clap for the color options.
+pub fn parse_color(s: &str) -> Result<Color, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("`{s}` is not a color, expected #RRGGBB"));
+    }
+
+    Ok(rgb(u32::from_str_radix(hex, 16).unwrap()))
+}
+
+/// Linear interpolation between two colors, `t` is in 0.0 - 1.0
+#[cfg(feature = "window")]
+pub fn mix(from: Color, to: Color, t: f32) -> Color {
+    let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+
+    Color::rgb(
+        channel(from.r, to.r),
+        channel(from.g, to.g),
+        channel(from.b, to.b),
+    )
+}
+
+const fn rgb(color: u32) -> Color {
+    Color::rgb((color >> 16) as u8, (color >> 8) as u8, color as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_colors() {
+        assert_eq!(parse_color("#FFB000"), Ok(Color::rgb(0xFF, 0xB0, 0x00)));
+        assert_eq!(parse_color("0f380f"), Ok(Color::rgb(0x0F, 0x38, 0x0F)));
+        assert!(parse_color("#FFF").is_err());
+        assert!(parse_color("#GGGGGG").is_err());
+        assert!(parse_color("+FFFFF").is_err());
+    }
+
+    #[test]
+    fn parse_theme_file() {
+        let palette = Palette::parse(
+            "# a comment\n\
+             background = #101010\n\
+             \n\
+             foreground = #F0F0F0\n\
+             color3=000080\n",
+        )
+        .unwrap();
+
+        assert_eq!(palette.background(), Color::rgb(0x10, 0x10, 0x10));
+        assert_eq!(palette.0[1], Color::rgb(0xF0, 0xF0, 0xF0));
+        assert_eq!(palette.0[2], Palette::default().0[2]);
+        assert_eq!(palette.0[3], Color::rgb(0x00, 0x00, 0x80));
+    }
+
+    #[test]
+    fn invalid_theme_file() {
+        assert!(Palette::parse("background #000000").is_err());
+        assert!(Palette::parse("border = #000000").is_err());
+        assert!(Palette::parse("foreground = white").is_err());
+    }
+
+    #[test]
+    fn builtin_theme() {
+        assert_eq!(Palette::load("octo").unwrap().background(), rgb(0x996600));
+    }
+}
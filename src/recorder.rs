@@ -0,0 +1,196 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{eyre::Context as _, Result};
+
+use chirp_8::machine::{Framebuffer, HEIGHT, WIDTH};
+
+use crate::{palette::Palette, screenshot};
+
+/// Frames per second of the recording in emulated time, the nominal rate of the CHIP-8 timers
+pub const FPS: u32 = 60;
+
+/// Records frames into an animated GIF if the path ends in `.gif`, otherwise into a directory
+/// of numbered PNGs. Sound is recorded separately with [`Wav`](crate::sound::Wav).
+pub struct Recorder {
+    output: Output,
+    palette: Palette,
+    scale: u32,
+    frames: u64,
+}
+
+enum Output {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        /// Last frame and how many times it was repeated, identical frames are merged
+        pending: Option<(Box<Framebuffer>, u64)>,
+    },
+    Png(PathBuf),
+}
+
+impl Recorder {
+    /// Starts recording to `path`.
+    pub fn start(path: &Path, palette: Palette, scale: u32) -> Result<Self> {
+        let scale = scale.max(1);
+
+        let output = if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("gif"))
+        {
+            let file = File::create(path)
+                .wrap_err_with(|| format!("failed to create {}", path.display()))?;
+            let colors = [palette.0[0], palette.0[1]]
+                .into_iter()
+                .flat_map(|c| [c.r, c.g, c.b])
+                .collect::<Vec<_>>();
+
+            let mut encoder = gif::Encoder::new(
+                BufWriter::new(file),
+                (WIDTH as u32 * scale) as u16,
+                (HEIGHT as u32 * scale) as u16,
+                &colors,
+            )
+            .wrap_err("failed to start the GIF")?;
+            encoder
+                .set_repeat(gif::Repeat::Infinite)
+                .wrap_err("failed to start the GIF")?;
+
+            Output::Gif {
+                encoder,
+                pending: None,
+            }
+        } else {
+            std::fs::create_dir_all(path)
+                .wrap_err_with(|| format!("failed to create {}", path.display()))?;
+
+            Output::Png(path.to_path_buf())
+        };
+
+        Ok(Self {
+            output,
+            palette,
+            scale,
+            frames: 0,
+        })
+    }
+
+    /// Adds one frame.
+    pub fn frame(&mut self, buffer: &Framebuffer) -> Result<()> {
+        match &mut self.output {
+            Output::Gif { encoder, pending } => match pending {
+                Some((last, repeats)) if **last == *buffer => *repeats += 1,
+                _ => {
+                    if let Some((last, repeats)) = pending.take() {
+                        write_gif_frame(
+                            encoder,
+                            &last,
+                            self.scale,
+                            self.frames - repeats,
+                            repeats,
+                        )?;
+                    }
+                    *pending = Some((Box::new(*buffer), 1));
+                }
+            },
+            Output::Png(dir) => {
+                let path = dir.join(format!("{:06}.png", self.frames));
+                screenshot::save(&path, buffer, &self.palette, self.scale)?;
+            }
+        }
+
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "window")]
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Writes out everything still buffered, returns the number of recorded frames.
+    pub fn finish(self) -> Result<u64> {
+        if let Output::Gif {
+            mut encoder,
+            pending: Some((last, repeats)),
+        } = self.output
+        {
+            write_gif_frame(
+                &mut encoder,
+                &last,
+                self.scale,
+                self.frames - repeats,
+                repeats,
+            )?;
+        }
+
+        Ok(self.frames)
+    }
+}
+
+/// Writes a frame shown from frame `start` for `repeats` frames. GIF delays are in
+/// hundredths of a second, so they are rounded such that the total length stays exact.
+fn write_gif_frame(
+    encoder: &mut gif::Encoder<BufWriter<File>>,
+    buffer: &Framebuffer,
+    scale: u32,
+    start: u64,
+    repeats: u64,
+) -> Result<()> {
+    let centis = |frame: u64| frame * 100 / FPS as u64;
+    let delay = centis(start + repeats) - centis(start);
+
+    let scale = scale as usize;
+    let (width, height) = (buffer.len() * scale, buffer[0].len() * scale);
+    let pixels = (0..width * height)
+        .map(|idx| buffer[idx % width / scale][idx / width / scale] as u8)
+        .collect::<Vec<_>>();
+
+    let frame = gif::Frame {
+        width: width as u16,
+        height: height as u16,
+        delay: delay.min(u16::MAX as u64) as u16,
+        buffer: Cow::Owned(pixels),
+        ..Default::default()
+    };
+
+    encoder
+        .write_frame(&frame)
+        .wrap_err("failed to write a GIF frame")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gif_merges_identical_frames() {
+        let dir = std::env::temp_dir().join(format!("chirp-8-recorder-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("clip.gif");
+
+        let mut buffer = [[false; HEIGHT as usize]; WIDTH as usize];
+        let mut recorder = Recorder::start(&path, Palette::default(), 1).unwrap();
+        for frame in 0..90 {
+            buffer[0][0] = frame >= 30;
+            recorder.frame(&buffer).unwrap();
+        }
+        assert_eq!(recorder.finish().unwrap(), 90);
+
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(File::open(&path).unwrap())
+            .unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        // 30 frames off and 60 frames on, 1.5 seconds in total
+        assert_eq!(delays, [50, 100]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+
+use chirp_8::{machine::Machine, memory::Access};
+
+use crate::condition::Condition;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Paused,
+    Running,
+    /// Instructions left to execute before pausing again
+    Stepping(u32),
+}
+
+/// Why the machine just paused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// At a breakpoint whose condition holds
+    Breakpoint,
+    /// After the instructions of a step
+    Stepped,
+    /// After the instruction at `pc` accessed a watched address
+    Watch { access: Access, pc: u16 },
+}
+
+/// When the machine runs and where it stops, shared by the debugger frontends. Watchpoints
+/// live in the machine's memory, this only reports their hits.
+#[derive(Debug)]
+pub struct RunControl {
+    breakpoints: BTreeMap<u16, Option<Condition>>,
+    state: State,
+    /// Breakpoint execution just continued from, it doesn't stop again right away
+    resumed_at: Option<u16>,
+}
+
+impl RunControl {
+    /// Starts paused on entry if `paused`, otherwise running.
+    pub fn new(paused: bool) -> Self {
+        Self {
+            breakpoints: BTreeMap::new(),
+            state: if paused {
+                State::Paused
+            } else {
+                State::Running
+            },
+            resumed_at: None,
+        }
+    }
+
+    /// Decides whether the next instruction runs, call it once before each. Returns why
+    /// the machine paused if that happened just now.
+    pub fn before_step(&mut self, machine: &Machine) -> Option<Stop> {
+        match self.state {
+            State::Running if self.resumed_at == Some(machine.pc) => {}
+            State::Running if self.breaks_at(machine) => {
+                self.state = State::Paused;
+                return Some(Stop::Breakpoint);
+            }
+            State::Running | State::Paused => {}
+            State::Stepping(0) => {
+                self.state = State::Paused;
+                return Some(Stop::Stepped);
+            }
+            State::Stepping(left) => self.state = State::Stepping(left - 1),
+        }
+
+        self.resumed_at = None;
+        None
+    }
+
+    /// Call after every instruction, `pc` is where it started. Pauses if it accessed a
+    /// watched address.
+    pub fn after_step(&mut self, machine: &mut Machine, pc: u16) -> Option<Stop> {
+        let access = machine.memory.take_hit()?;
+        self.state = State::Paused;
+
+        Some(Stop::Watch { access, pc })
+    }
+
+    pub fn paused(&self) -> bool {
+        self.state == State::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.state = State::Paused;
+    }
+
+    /// Runs until a breakpoint, leaving the one at `pc` first.
+    pub fn resume(&mut self, machine: &Machine) {
+        self.state = State::Running;
+        self.resumed_at = Some(machine.pc);
+    }
+
+    /// Executes `count` instructions and pauses again.
+    pub fn step(&mut self, count: u32) {
+        self.state = State::Stepping(count);
+    }
+
+    pub fn breakpoints(&self) -> &BTreeMap<u16, Option<Condition>> {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut BTreeMap<u16, Option<Condition>> {
+        &mut self.breakpoints
+    }
+
+    fn breaks_at(&self, machine: &Machine) -> bool {
+        match self.breakpoints.get(&machine.pc) {
+            Some(Some(condition)) => condition.holds(machine),
+            Some(None) => true,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chirp_8::machine::Quirks;
+
+    use super::*;
+
+    #[test]
+    fn leaves_the_breakpoint_it_resumed_at() {
+        // V0 += 1, jump back
+        let mut machine = Machine::new(&[0x70, 0x01, 0x12, 0x00], Quirks::default());
+        let mut control = RunControl::new(true);
+        control.breakpoints_mut().insert(0x200, None);
+
+        assert_eq!(control.before_step(&machine), None);
+        assert!(control.paused());
+
+        control.resume(&machine);
+        assert_eq!(control.before_step(&machine), None);
+        machine.step().unwrap();
+        assert_eq!(control.before_step(&machine), None);
+        machine.step().unwrap();
+        assert_eq!(control.before_step(&machine), Some(Stop::Breakpoint));
+
+        control.step(1);
+        assert_eq!(control.before_step(&machine), None);
+        machine.step().unwrap();
+        assert_eq!(control.before_step(&machine), Some(Stop::Stepped));
+        assert!(control.paused());
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::Path;
+
+use color_eyre::{eyre::Context as _, Result};
+
+use chirp_8::machine::Framebuffer;
+
+use crate::{filter::Image, palette::Palette};
+
+/// Saves the framebuffer, the format is picked by the extension: `.pbm` is a 1-bit image at
+/// native resolution, anything else a PNG in the palette's colors with every pixel scaled up
+/// to `scale` x `scale`.
+pub fn save(path: &Path, buffer: &Framebuffer, palette: &Palette, scale: u32) -> Result<()> {
+    let data = match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("pbm") => pbm(buffer),
+        _ => png(&colorize(buffer, palette).upscale(scale.max(1) as usize))?,
+    };
+
+    std::fs::write(path, data).wrap_err_with(|| format!("failed to write {}", path.display()))
+}
+
+/// Binary (P4) PBM, set pixels are black
+pub fn pbm(buffer: &Framebuffer) -> Vec<u8> {
+    let (width, height) = (buffer.len(), buffer[0].len());
+    let mut data = format!("P4\n{width} {height}\n").into_bytes();
+
+    for y in 0..height {
+        // rows are padded to whole bytes, the first pixel is the most significant bit
+        for columns in buffer.chunks(8) {
+            let bits = columns
+                .iter()
+                .enumerate()
+                .fold(0u8, |bits, (i, column)| bits | (column[y] as u8) << (7 - i));
+
+            data.push(bits);
+        }
+    }
+
+    data
+}
+
+pub fn png(image: &Image) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+
+    let mut encoder = png::Encoder::new(&mut data, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&image.pixels))
+        .wrap_err("failed to encode PNG")?;
+
+    Ok(data)
+}
+
+/// The framebuffer in the palette's colors, at native resolution
+pub fn colorize(buffer: &Framebuffer, palette: &Palette) -> Image {
+    let mut image = Image::new(buffer.len(), buffer[0].len());
+
+    for (x, column) in buffer.iter().enumerate() {
+        for (y, &on) in column.iter().enumerate() {
+            let color = palette.0[on as usize];
+            image.set(x, y, [color.r, color.g, color.b]);
+        }
+    }
+
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use chirp_8::machine::{HEIGHT, WIDTH};
+
+    use super::*;
+
+    #[test]
+    fn pbm_bits() {
+        let mut buffer = [[false; HEIGHT as usize]; WIDTH as usize];
+        buffer[0][0] = true;
+        buffer[9][0] = true;
+        buffer[63][31] = true;
+
+        let data = pbm(&buffer);
+        let (header, pixels) = data.split_at(b"P4\n64 32\n".len());
+
+        assert_eq!(header, b"P4\n64 32\n");
+        assert_eq!(pixels.len(), 8 * 32);
+        assert_eq!(&pixels[..2], [0b1000_0000, 0b0100_0000]);
+        assert_eq!(pixels[8 * 32 - 1], 0b0000_0001);
+    }
+
+    #[test]
+    fn png_signature() {
+        let image = colorize(
+            &[[false; HEIGHT as usize]; WIDTH as usize],
+            &Palette::default(),
+        );
+
+        assert!(png(&image.upscale(2))
+            .unwrap()
+            .starts_with(b"\x89PNG\r\n\x1a\n"));
+    }
+}
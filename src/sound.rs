@@ -1,76 +1,314 @@
-use std::{f32::consts::PI, time::Duration};
+use std::{collections::VecDeque, fs::File, io::BufWriter, path::Path, time::Duration};
 
-use rodio::source::Source;
+use clap::ValueEnum;
+use color_eyre::{eyre::Context as _, Result};
 
-/// An infinite source that produces a saw tooth wave.
-/// Has a definable sample rate and one channel.
+use chirp_8::machine::SoundEvent;
+
+/// Amplitude at 100% volume. Full scale waves are unpleasantly loud, and this leaves
+/// headroom so the envelope never clips.
+const MAX_AMPLITUDE: f32 = 0.25;
+
+/// Shape of the beeper's tone
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    #[default]
+    Saw,
+    /// White noise, a new random level every period of the frequency
+    Noise,
+}
+
+/// Everything about how the beeper sounds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub frequency: f32,
+    pub waveform: Waveform,
+    /// Volume from 0.0 to 1.0
+    pub volume: f32,
+    /// How long it takes to fade in when the sound timer starts
+    pub attack: Duration,
+    /// How long it takes to fade out when the sound timer runs out
+    pub release: Duration,
+}
+
+/// An infinite stream of mono samples playing the tone while it's on. Turning it on and off
+/// fades the tone in and out instead of cutting it off, which would click.
 #[derive(Debug, Clone)]
-pub struct SawWave {
-    freq: f32,
-    number_of_samples: usize,
+pub struct Beeper {
+    waveform: Waveform,
     sample_rate: u32,
+    /// Samples played so far, the phase is computed from it so rounding errors don't add up
+    samples: u64,
+    /// How many periods pass per sample
+    phase_step: f64,
+    /// Position in the current period, from 0.0 to 1.0
+    phase: f32,
+    amplitude: f32,
+    /// Current envelope level, from 0.0 to 1.0
+    level: f32,
+    attack_step: f32,
+    release_step: f32,
+    on: bool,
+    /// Xorshift state for the noise waveform, and the level it produced
+    noise: u32,
+    noise_level: f32,
 }
 
-impl SawWave {
-    /// The frequency and sample rate of the saw tooth wave.
-    #[inline]
-    pub fn new(freq: f32, sample_rate: u32) -> SawWave {
-        SawWave {
-            freq,
+impl Beeper {
+    pub fn new(tone: Tone, sample_rate: u32) -> Self {
+        // the number of samples to go from 0.0 to 1.0, at least one
+        let step =
+            |duration: Duration| 1.0 / (duration.as_secs_f32() * sample_rate as f32).max(1.0);
+
+        Self {
+            waveform: tone.waveform,
             sample_rate,
-            ..Default::default()
+            samples: 0,
+            phase_step: tone.frequency as f64 / sample_rate as f64,
+            phase: 0.0,
+            amplitude: tone.volume.clamp(0.0, 1.0) * MAX_AMPLITUDE,
+            level: 0.0,
+            attack_step: step(tone.attack),
+            release_step: step(tone.release),
+            on: false,
+            noise: 0x2545_F491,
+            noise_level: 0.0,
         }
     }
-}
 
-impl Default for SawWave {
-    /// Defines a saw tooth wave of 440Hz (A above middle C)
-    /// with a sample rate of 48000.
-    #[inline]
-    fn default() -> Self {
-        Self {
-            freq: 440.0,
-            number_of_samples: 0,
-            sample_rate: 48000,
+    #[cfg(feature = "window")]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Starts or stops the tone, the beeper starts out silent.
+    pub fn set(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    /// The waveform at the current phase, from -1.0 to 1.0
+    fn wave(&self) -> f32 {
+        let phase = self.phase;
+
+        match self.waveform {
+            Waveform::Square if phase < 0.5 => 1.0,
+            Waveform::Square => -1.0,
+            Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Saw => 2.0 * phase - 1.0,
+            Waveform::Noise => self.noise_level,
         }
     }
 }
 
-impl Iterator for SawWave {
+impl Iterator for Beeper {
     type Item = f32;
 
     #[inline]
     fn next(&mut self) -> Option<f32> {
-        self.number_of_samples = self.number_of_samples.wrapping_add(1);
+        self.level = match self.on {
+            true => (self.level + self.attack_step).min(1.0),
+            false => (self.level - self.release_step).max(0.0),
+        };
+
+        let sample = self.wave() * self.amplitude * self.level;
 
-        let value = 2.0 / PI
-            * (self.freq * (PI * self.number_of_samples as f32) / self.sample_rate as f32)
-                .tan()
-                .recip()
-                .atan();
+        let previous = self.samples as f64 * self.phase_step;
+        self.samples += 1;
+        let periods = self.samples as f64 * self.phase_step;
+        self.phase = periods.fract() as f32;
+
+        // a new period started
+        if periods.floor() > previous.floor() {
+            self.noise ^= self.noise << 13;
+            self.noise ^= self.noise >> 17;
+            self.noise ^= self.noise << 5;
+            self.noise_level = self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0;
+        }
 
-        Some(value)
+        Some(sample)
     }
 }
 
-impl Source for SawWave {
-    #[inline]
-    fn current_frame_len(&self) -> Option<usize> {
-        None
+/// Renders sound events into a WAV file, every sample is placed by emulated time so the
+/// file doesn't depend on an audio device or on how fast the emulator ran.
+pub struct Wav {
+    writer: hound::WavWriter<BufWriter<File>>,
+    beeper: Beeper,
+    ips: u64,
+    /// Cycle the file starts at
+    start: u64,
+    /// Events pushed but not reached by the written samples yet
+    pending: VecDeque<SoundEvent>,
+    written: u64,
+}
+
+impl Wav {
+    pub const SAMPLE_RATE: u32 = 48000;
+
+    /// Creates the file, its first sample plays at emulated time `start`.
+    pub fn create(path: &Path, tone: Tone, ips: u64, start: u64) -> Result<Self> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: Self::SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        Ok(Self {
+            writer: hound::WavWriter::create(path, spec)
+                .wrap_err_with(|| format!("failed to create {}", path.display()))?,
+            beeper: Beeper::new(tone, Self::SAMPLE_RATE),
+            ips: ips.max(1),
+            start,
+            pending: VecDeque::new(),
+            written: 0,
+        })
     }
 
-    #[inline]
-    fn channels(&self) -> u16 {
-        1
+    /// Queues sound events, events from before the last written sample take effect right away.
+    pub fn push(&mut self, events: &[SoundEvent]) {
+        self.pending.extend(events);
     }
 
-    #[inline]
-    fn sample_rate(&self) -> u32 {
-        self.sample_rate
+    /// Writes the samples up to emulated time `cycle`.
+    pub fn write_until(&mut self, cycle: u64) -> Result<()> {
+        let end = self.sample_at(cycle);
+
+        while self.written < end {
+            while let Some(event) = self.pending.front() {
+                if self.sample_at(event.cycle) > self.written {
+                    break;
+                }
+
+                self.beeper.set(event.on);
+                self.pending.pop_front();
+            }
+
+            let sample = self.beeper.next().unwrap_or_default();
+            self.writer
+                .write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+                .wrap_err("failed to write audio")?;
+            self.written += 1;
+        }
+
+        Ok(())
     }
 
-    #[inline]
-    fn total_duration(&self) -> Option<Duration> {
-        None
+    pub fn finish(self) -> Result<()> {
+        self.writer.finalize().wrap_err("failed to write audio")
+    }
+
+    fn sample_at(&self, cycle: u64) -> u64 {
+        let cycles = cycle.saturating_sub(self.start) as u128;
+
+        (cycles * self.beeper.sample_rate as u128 / self.ips as u128) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(waveform: Waveform) -> Tone {
+        Tone {
+            frequency: 1000.0,
+            waveform,
+            volume: 1.0,
+            attack: Duration::ZERO,
+            release: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn silent_until_gate_opens() {
+        let mut beeper = Beeper::new(tone(Waveform::Square), 48000);
+
+        assert!(beeper.by_ref().take(100).all(|sample| sample == 0.0));
+    }
+
+    #[test]
+    fn frequency() {
+        let mut beeper = Beeper::new(tone(Waveform::Square), 48000);
+        beeper.set(true);
+
+        // one second of a 1 kHz square wave starting high goes from low to high 1000 times,
+        // the last time on the first sample of the next second
+        let samples = beeper.take(48001).collect::<Vec<_>>();
+        let rising = samples
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] > 0.0)
+            .count();
+
+        assert_eq!(rising, 1000);
+        assert_eq!(samples[0], MAX_AMPLITUDE);
+    }
+
+    #[test]
+    fn release_fades_out() {
+        let mut beeper = Beeper::new(tone(Waveform::Saw), 48000);
+
+        beeper.set(true);
+        beeper.by_ref().take(10).for_each(drop);
+        assert_eq!(beeper.level, 1.0);
+
+        // 1 ms is 48 samples
+        beeper.set(false);
+        let mut previous = beeper.level;
+        for _ in 0..40 {
+            beeper.next();
+            assert!(beeper.level < previous);
+            previous = beeper.level;
+        }
+
+        beeper.by_ref().take(10).for_each(drop);
+        assert_eq!(beeper.level, 0.0);
+    }
+
+    #[test]
+    fn waveforms_in_range() {
+        for waveform in Waveform::value_variants() {
+            let mut beeper = Beeper::new(tone(*waveform), 48000);
+            beeper.set(true);
+
+            assert!(
+                beeper.take(4800).all(|s| s.abs() <= MAX_AMPLITUDE),
+                "{waveform:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn wav_samples_follow_emulated_time() {
+        let path = std::env::temp_dir().join(format!("chirp-8-sound-{}.wav", std::process::id()));
+
+        // 600 instructions per second make 80 samples per instruction
+        let mut wav = Wav::create(&path, tone(Waveform::Square), 600, 100).unwrap();
+        wav.push(&[SoundEvent {
+            cycle: 110,
+            on: true,
+        }]);
+        wav.write_until(150).unwrap();
+        wav.push(&[SoundEvent {
+            cycle: 200,
+            on: false,
+        }]);
+        wav.write_until(400).unwrap();
+        wav.finish().unwrap();
+
+        let samples = hound::WavReader::open(&path)
+            .unwrap()
+            .into_samples::<i16>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(samples.len(), 300 * 80);
+        assert_eq!(samples.iter().position(|&s| s != 0), Some(10 * 80));
+        // the release takes 48 samples
+        assert_eq!(samples.iter().rposition(|&s| s != 0), Some(100 * 80 + 46));
     }
 }
@@ -0,0 +1,288 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use color_eyre::{
+    eyre::{eyre, Context as _},
+    Result,
+};
+use rodio::{source::Source, OutputStream, Sink};
+use sdl2::{
+    audio::{AudioQueue, AudioSpecDesired},
+    Sdl,
+};
+
+use chirp_8::machine::SoundEvent;
+
+use crate::sound::{Beeper, Tone};
+
+/// How far the live beeper plays behind the emulator, so sound events arrive before they're
+/// due even if the emulator gets scheduled late.
+const LATENCY: Duration = Duration::from_millis(50);
+/// How many samples the player generates between looking for new sound events
+const POLL_SAMPLES: u64 = 64;
+/// Samples kept in the SDL audio queue, and how many are added at once
+const SDL_QUEUED: u32 = 2048;
+const SDL_CHUNK: usize = 512;
+
+/// Hands the machine's sound events over to a [`Player`] on the audio thread
+#[derive(Debug, Clone, Default)]
+pub struct Events(Arc<Mutex<VecDeque<SoundEvent>>>);
+
+impl Events {
+    pub fn send(&self, events: Vec<SoundEvent>) {
+        if !events.is_empty() {
+            self.0.lock().unwrap().extend(events);
+        }
+    }
+}
+
+/// Plays a [`Beeper`] live, turning it on and off at the emulated time of each sound event
+/// rather than whenever the event happens to arrive.
+///
+/// Events are placed at `origin + cycle * sample_rate / ips` samples. The origin is picked
+/// by the first event to play [`LATENCY`] later, and picked again whenever an event would be
+/// late or far ahead, which happens when the emulator was paused or can't keep up.
+#[derive(Debug)]
+pub struct Player {
+    beeper: Beeper,
+    events: Events,
+    /// Events taken from `events`, waiting for their sample
+    pending: VecDeque<SoundEvent>,
+    ips: u64,
+    /// Samples played so far
+    played: u64,
+    origin: Option<i64>,
+}
+
+impl Player {
+    pub fn new(beeper: Beeper, events: Events, ips: u64) -> Self {
+        Self {
+            beeper,
+            events,
+            pending: VecDeque::new(),
+            ips: ips.max(1),
+            played: 0,
+            origin: None,
+        }
+    }
+
+    /// Turns the beeper on and off for the events due at the current sample.
+    fn apply_events(&mut self) {
+        let latency = (LATENCY.as_secs_f64() * self.beeper.sample_rate() as f64) as i64;
+        let played = self.played as i64;
+
+        while let Some(event) = self.pending.front() {
+            let offset =
+                (event.cycle as u128 * self.beeper.sample_rate() as u128 / self.ips as u128) as i64;
+
+            let origin = match self.origin {
+                Some(origin) if (played..=played + 4 * latency).contains(&(origin + offset)) => {
+                    origin
+                }
+                _ => played + latency - offset,
+            };
+            self.origin = Some(origin);
+
+            if origin + offset > played {
+                break;
+            }
+
+            self.beeper.set(event.on);
+            self.pending.pop_front();
+        }
+    }
+}
+
+impl Iterator for Player {
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.played.is_multiple_of(POLL_SAMPLES) {
+            if let Ok(mut events) = self.events.0.try_lock() {
+                self.pending.extend(events.drain(..));
+            }
+        }
+
+        if !self.pending.is_empty() {
+            self.apply_events();
+        }
+        self.played += 1;
+
+        self.beeper.next()
+    }
+}
+
+impl Source for Player {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.beeper.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Which library plays the sound
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    #[default]
+    Rodio,
+    /// SDL's audio queue
+    Sdl,
+    /// No sound at all
+    None,
+}
+
+/// The audio device playing the beeper, kept open as long as it lives
+pub enum Speaker {
+    Rodio {
+        _stream: OutputStream,
+        _sink: Sink,
+        events: Events,
+    },
+    /// The queue is filled from the emulator's thread by [`Speaker::send`]
+    Sdl {
+        queue: AudioQueue<f32>,
+        player: Box<Player>,
+        events: Events,
+    },
+    Silent,
+}
+
+impl Speaker {
+    /// Opens the device of `backend`. Running without sound beats not running at all, so if
+    /// that fails the speaker stays silent.
+    pub fn open(backend: Backend, context: &Sdl, tone: Tone, ips: u64) -> Self {
+        let speaker = match backend {
+            Backend::Rodio => Self::rodio(tone, ips),
+            Backend::Sdl => Self::sdl(context, tone, ips),
+            Backend::None => Ok(Self::Silent),
+        };
+
+        speaker.unwrap_or_else(|err| {
+            eprintln!("warning: {err}, sound is disabled");
+            Self::Silent
+        })
+    }
+
+    fn rodio(tone: Tone, ips: u64) -> Result<Self> {
+        let (stream, handle) =
+            OutputStream::try_default().wrap_err("failed to open the rodio audio device")?;
+        let sink = Sink::try_new(&handle).wrap_err("failed to open the rodio audio device")?;
+
+        let events = Events::default();
+        sink.append(Player::new(Beeper::new(tone, 48000), events.clone(), ips));
+
+        Ok(Self::Rodio {
+            _stream: stream,
+            _sink: sink,
+            events,
+        })
+    }
+
+    fn sdl(context: &Sdl, tone: Tone, ips: u64) -> Result<Self> {
+        let spec = AudioSpecDesired {
+            freq: Some(48000),
+            channels: Some(1),
+            samples: None,
+        };
+        let queue = context
+            .audio()
+            .and_then(|audio| audio.open_queue::<f32, _>(None, &spec))
+            .map_err(|err| eyre!("failed to open the SDL audio device: {err}"))?;
+        queue.resume();
+
+        // the device may not take 48 kHz
+        let sample_rate = queue.spec().freq as u32;
+        let events = Events::default();
+
+        Ok(Self::Sdl {
+            player: Box::new(Player::new(
+                Beeper::new(tone, sample_rate),
+                events.clone(),
+                ips,
+            )),
+            queue,
+            events,
+        })
+    }
+
+    /// Passes the machine's sound events on to the player.
+    pub fn send(&mut self, new_events: Vec<SoundEvent>) {
+        match self {
+            Self::Rodio { events, .. } => events.send(new_events),
+            Self::Sdl {
+                queue,
+                player,
+                events,
+            } => {
+                events.send(new_events);
+
+                let queued = SDL_QUEUED * std::mem::size_of::<f32>() as u32;
+                while queue.size() < queued {
+                    let chunk = player.by_ref().take(SDL_CHUNK).collect::<Vec<_>>();
+                    if queue.queue_audio(&chunk).is_err() {
+                        break;
+                    }
+                }
+            }
+            Self::Silent => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sound::Waveform;
+
+    #[test]
+    fn events_keep_their_spacing() {
+        let events = Events::default();
+        // 600 instructions per second make 80 samples per instruction
+        let tone = Tone {
+            frequency: 1000.0,
+            waveform: Waveform::Square,
+            volume: 1.0,
+            attack: Duration::ZERO,
+            release: Duration::from_millis(1),
+        };
+        let mut player = Player::new(Beeper::new(tone, 48000), events.clone(), 600);
+
+        // both arrive at once, but play 1/6 s apart
+        events.send(vec![
+            SoundEvent {
+                cycle: 60,
+                on: true,
+            },
+            SoundEvent {
+                cycle: 160,
+                on: false,
+            },
+        ]);
+        let samples = player.by_ref().take(48000).collect::<Vec<_>>();
+
+        let start = samples.iter().position(|&s| s != 0.0).unwrap();
+        let end = samples.iter().rposition(|&s| s != 0.0).unwrap();
+        assert_eq!(start, (LATENCY.as_secs_f64() * 48000.0) as usize);
+        // the release takes 48 samples
+        assert_eq!(end - start, 100 * 80 + 47);
+    }
+}
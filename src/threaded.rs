@@ -0,0 +1,196 @@
+use std::rc::Rc;
+
+use color_eyre::Result;
+
+use crate::{
+    instruction::{Instruction, MAX_BLOCK_LEN},
+    machine::Machine,
+    memory::RegIdx,
+};
+
+/// A single compiled instruction, called with `pc` already pointing past it
+type Op = Box<dyn Fn(&mut Machine) -> Result<()>>;
+
+/// A straight-line run of instructions, the last one is the only one that may change `pc`
+/// or write to memory.
+struct Block {
+    start: u16,
+    /// Address right after the last instruction
+    end: u16,
+    /// Compiled instructions, each paired with the address following it
+    ops: Vec<(u16, Op)>,
+}
+
+impl Block {
+    fn overlaps(&self, (lo, hi): (u16, u16)) -> bool {
+        self.start <= hi && lo < self.end
+    }
+}
+
+/// An execution engine that compiles basic blocks into chains of closures.
+///
+/// It produces exactly the same results as [`Machine::step`], blocks are recompiled whenever
+/// memory they were compiled from gets written to.
+pub struct Threaded {
+    /// Compiled blocks, indexed by their starting address
+    blocks: Vec<Option<Rc<Block>>>,
+}
+
+impl Threaded {
+    pub fn new() -> Self {
+        Self {
+            blocks: vec![None; 4 * 1024],
+        }
+    }
+
+    /// Runs the block starting at the machine's `pc`, returns how many instructions it executed.
+    pub fn run_block(&mut self, machine: &mut Machine) -> Result<usize> {
+        let block = match self.blocks.get(machine.pc as usize) {
+            Some(Some(block)) => Rc::clone(block),
+            _ => self.compile(machine),
+        };
+
+        let mut result = Ok(block.ops.len());
+        for (next, op) in &block.ops {
+            machine.pc = *next;
+            machine.cycles += 1;
+
+            if let Err(err) = op(machine) {
+                result = Err(err);
+                break;
+            }
+        }
+
+        if let Some(written) = machine.memory.take_written() {
+            self.invalidate(written);
+        }
+
+        result
+    }
+
+    /// Runs blocks until at least `count` instructions were executed, returns the exact number.
+    pub fn run(&mut self, machine: &mut Machine, count: usize) -> Result<usize> {
+        let mut executed = 0;
+
+        while executed < count {
+            executed += self.run_block(machine)?;
+        }
+
+        Ok(executed)
+    }
+
+    /// Drops the blocks compiled from the written range. Blocks are indexed by their start and
+    /// at most [`MAX_BLOCK_LEN`] instructions long, so only the slots up to that far
+    /// before the range can hold one.
+    fn invalidate(&mut self, written: (u16, u16)) {
+        let (lo, hi) = written;
+        let first = lo.saturating_sub(MAX_BLOCK_LEN as u16 * 2 - 1) as usize;
+        let last = (hi as usize).min(self.blocks.len() - 1);
+
+        for slot in self.blocks.get_mut(first..=last).unwrap_or_default() {
+            if slot.as_ref().is_some_and(|block| block.overlaps(written)) {
+                *slot = None;
+            }
+        }
+    }
+
+    fn compile(&mut self, machine: &mut Machine) -> Rc<Block> {
+        let start = machine.pc;
+        let mut ops = Vec::new();
+        let mut pc = start;
+
+        loop {
+            let inst = match machine.memory.read_instruction(pc) {
+                Ok(Some(inst)) => inst,
+                // let the interpreter report the error once execution gets here
+                _ => {
+                    ops.push((pc + 2, undecodable(pc)));
+                    pc += 2;
+                    break;
+                }
+            };
+
+            pc += 2;
+            ops.push((pc, compile_op(inst)));
+
+            if inst.ends_block() || ops.len() == MAX_BLOCK_LEN {
+                break;
+            }
+        }
+
+        let block = Rc::new(Block {
+            start,
+            end: pc,
+            ops,
+        });
+
+        if let Some(slot) = self.blocks.get_mut(start as usize) {
+            *slot = Some(Rc::clone(&block));
+        }
+
+        block
+    }
+}
+
+impl Default for Threaded {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn compile_op(inst: Instruction) -> Op {
+    use Instruction::*;
+
+    // the hottest instructions get specialized closures, everything else goes through the
+    // interpreter so the semantics can't drift apart
+    match inst {
+        Jump(nnn) => Box::new(move |m| {
+            m.pc = nnn;
+            Ok(())
+        }),
+        SetIm(vx, nn) => Box::new(move |m| {
+            m.regs[vx] = nn;
+            Ok(())
+        }),
+        AddIm(vx, nn) => Box::new(move |m| {
+            m.regs[vx] = m.regs[vx].wrapping_add(nn);
+            Ok(())
+        }),
+        SetIndex(nnn) => Box::new(move |m| {
+            m.index = nnn;
+            Ok(())
+        }),
+        Set(vx, vy) => Box::new(move |m| {
+            m.regs[vx] = m.regs[vy];
+            Ok(())
+        }),
+        Add(vx, vy) => Box::new(move |m| {
+            let (result, overflow) = m.regs[vx].overflowing_add(m.regs[vy]);
+
+            (m.regs[vx], m.regs[RegIdx::FLAG]) = (result, overflow as u8);
+            Ok(())
+        }),
+        SkipEqIm(vx, nn) => Box::new(move |m| {
+            if m.regs[vx] == nn {
+                m.pc += 2;
+            }
+            Ok(())
+        }),
+        SkipNeIm(vx, nn) => Box::new(move |m| {
+            if m.regs[vx] != nn {
+                m.pc += 2;
+            }
+            Ok(())
+        }),
+        _ => Box::new(move |m| m.execute(inst)),
+    }
+}
+
+fn undecodable(pc: u16) -> Op {
+    // the interpreter counts the instruction again if it turns out to be one
+    Box::new(move |m| {
+        m.pc = pc;
+        m.cycles -= 1;
+        m.step()
+    })
+}
@@ -0,0 +1,345 @@
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use color_eyre::{eyre::Context as _, Result};
+use crossterm::{
+    cursor,
+    event::{
+        self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    queue,
+    style::{self, Print, SetColors},
+    terminal,
+};
+
+use chirp_8::machine::{Framebuffer, Machine};
+
+use crate::{
+    palette::{Color, Palette},
+    Args, DEFAULT_SCALE,
+};
+
+/// How the terminal frontend draws the framebuffer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Graphics {
+    /// Two pixels per character with `▀`, in the theme's colors
+    #[default]
+    HalfBlock,
+    /// Eight pixels per character with Braille patterns, for small terminals
+    Braille,
+    /// A real image with sixel graphics, if the terminal supports them
+    Sixel,
+}
+
+/// Without key release events from the terminal, a key counts as released once it wasn't
+/// repeated for this long. It has to cover the delay before auto-repeat kicks in.
+const RELEASE_DELAY: Duration = Duration::from_millis(500);
+/// How often the screen is redrawn at most
+const FRAME_PERIOD: Duration = Duration::from_micros(16_667);
+
+/// Runs the ROM in the terminal, drawing with `--tui-graphics` and ringing the bell for sound.
+/// Esc or Ctrl+C quits.
+pub fn run(rom: &[u8], args: &Args) -> Result<()> {
+    let mut machine = Machine::new(rom, args.quirks());
+    let palette = args.palette()?;
+    let scale = args.scale.unwrap_or(DEFAULT_SCALE);
+    let keymap = args.keymap()?;
+
+    let terminal = Terminal::enter()?;
+    let mut out = std::io::stdout().lock();
+
+    // when each key was last pressed or repeated, to release them without release events
+    let mut pressed_at = [None::<Instant>; 16];
+    let mut shown = None::<Framebuffer>;
+    let mut last_frame = Instant::now();
+    let mut now = Instant::now();
+
+    loop {
+        while event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(key) => {
+                    let ctrl_c = key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL);
+                    if key.code == KeyCode::Esc || ctrl_c {
+                        return Ok(());
+                    }
+
+                    let KeyCode::Char(c) = key.code else {
+                        continue;
+                    };
+                    if let Some(idx) = keymap.get_char(c) {
+                        let pressed = key.kind != KeyEventKind::Release;
+
+                        machine.set_key(idx, pressed);
+                        pressed_at[idx as usize] = pressed.then(Instant::now);
+                    }
+                }
+                // everything has to be drawn again
+                Event::Resize(..) => {
+                    queue!(out, terminal::Clear(terminal::ClearType::All))?;
+                    shown = None;
+                }
+                _ => {}
+            }
+        }
+
+        if !terminal.release_events {
+            for (key, pressed_at) in pressed_at.iter_mut().enumerate() {
+                if pressed_at.is_some_and(|at| at.elapsed() > RELEASE_DELAY) {
+                    machine.set_key(key as u8, false);
+                    *pressed_at = None;
+                }
+            }
+        }
+
+        machine.step()?;
+
+        let millis = now.elapsed().as_millis();
+        if millis > args.timer_period {
+            machine.tick_timers((millis / args.timer_period) as u8);
+            now = Instant::now();
+        }
+
+        // the bell can't be held, ring it whenever a beep starts
+        if machine.take_sound_events().iter().any(|event| event.on) {
+            queue!(out, Print('\x07'))?;
+        }
+
+        if last_frame.elapsed() >= FRAME_PERIOD && shown != Some(machine.framebuffer) {
+            let frame = match args.tui_graphics {
+                Graphics::HalfBlock => half_blocks(&machine.framebuffer, &palette),
+                Graphics::Braille => braille(&machine.framebuffer, &palette),
+                Graphics::Sixel => sixel(&machine.framebuffer, &palette, scale),
+            };
+
+            queue!(out, cursor::MoveTo(0, 0))?;
+            out.write_all(frame.as_bytes())?;
+            shown = Some(machine.framebuffer);
+            last_frame = Instant::now();
+        }
+        out.flush()?;
+
+        std::thread::sleep(Duration::from_secs_f64(1f64 / args.ips as f64));
+    }
+}
+
+/// Raw mode on an alternate screen, restored when dropped
+struct Terminal {
+    /// Whether the terminal reports key releases, see [`RELEASE_DELAY`]
+    release_events: bool,
+}
+
+impl Terminal {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode().wrap_err("failed to enable raw mode")?;
+
+        let mut out = std::io::stdout();
+        crossterm::execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+        let release_events = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if release_events {
+            crossterm::execute!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        Ok(Self { release_events })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut out = std::io::stdout();
+        if self.release_events {
+            let _ = crossterm::execute!(out, PopKeyboardEnhancementFlags);
+        }
+
+        let _ = crossterm::execute!(
+            out,
+            style::ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// One character per two pixels stacked vertically, the top pixel is the foreground color
+/// of `▀` and the bottom one its background.
+fn half_blocks(buffer: &Framebuffer, palette: &Palette) -> String {
+    let height = buffer[0].len();
+    let mut out = String::new();
+
+    for y in (0..height).step_by(2) {
+        // colors are only sent when they change
+        let mut last = None;
+
+        for column in buffer {
+            let top = palette.0[column[y] as usize];
+            let bottom = palette.0[column.get(y + 1).copied().unwrap_or_default() as usize];
+
+            if last != Some((top, bottom)) {
+                let colors = style::Colors::new(color(top), color(bottom));
+                out += &format!("{}", SetColors(colors));
+                last = Some((top, bottom));
+            }
+            out.push('▀');
+        }
+
+        out += &format!("{}", style::ResetColor);
+        if y + 2 < height {
+            out += "\r\n";
+        }
+    }
+
+    out
+}
+
+/// One Braille pattern per 2x4 pixels
+fn braille(buffer: &Framebuffer, palette: &Palette) -> String {
+    /// Bit of the pattern for each pixel of a cell, indexed by `[x][y]`
+    const DOTS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+    let (width, height) = (buffer.len(), buffer[0].len());
+    let colors = style::Colors::new(color(palette.0[1]), color(palette.0[0]));
+    let mut out = format!("{}", SetColors(colors));
+
+    for cell_y in (0..height).step_by(4) {
+        for cell_x in (0..width).step_by(2) {
+            let mut bits = 0;
+            for (dx, dots) in DOTS.iter().enumerate() {
+                for (dy, dot) in dots.iter().enumerate() {
+                    let (x, y) = (cell_x + dx, cell_y + dy);
+                    if x < width && y < height && buffer[x][y] {
+                        bits |= dot;
+                    }
+                }
+            }
+
+            out.push(char::from_u32(0x2800 + bits as u32).unwrap());
+        }
+
+        if cell_y + 4 < height {
+            out += "\r\n";
+        }
+    }
+
+    out + &format!("{}", style::ResetColor)
+}
+
+/// The framebuffer as a sixel image with every pixel scaled to `scale` x `scale`
+fn sixel(buffer: &Framebuffer, palette: &Palette, scale: u32) -> String {
+    let scale = scale.max(1) as usize;
+    let (width, height) = (buffer.len() * scale, buffer[0].len() * scale);
+    let pixel = |x: usize, y: usize| y < height && buffer[x / scale][y / scale];
+
+    // raster attributes, then color registers in percent
+    let mut out = format!("\x1bPq\"1;1;{width};{height}");
+    for (register, color) in palette.0[..2].iter().enumerate() {
+        let percent = |c: u8| c as u32 * 100 / 255;
+        out += &format!(
+            "#{register};2;{};{};{}",
+            percent(color.r),
+            percent(color.g),
+            percent(color.b)
+        );
+    }
+
+    // every character is a column of six pixels, each color is drawn over the same band
+    for band in (0..height).step_by(6) {
+        for register in [0, 1] {
+            out += &format!("#{register}");
+
+            let mut run = None::<(char, usize)>;
+            for x in 0..width {
+                let bits = (0..6)
+                    .filter(|dy| pixel(x, band + dy) == (register == 1))
+                    .filter(|dy| band + dy < height)
+                    .fold(0, |bits, dy| bits | 1 << dy);
+                let c = char::from(63 + bits as u8);
+
+                run = match run {
+                    Some((prev, count)) if prev == c => Some((c, count + 1)),
+                    _ => {
+                        push_run(&mut out, run);
+                        Some((c, 1))
+                    }
+                };
+            }
+            push_run(&mut out, run);
+
+            out.push('$');
+        }
+
+        out.push('-');
+    }
+
+    out + "\x1b\\"
+}
+
+/// Writes a run of sixel characters, repeats are compressed with `!<count>`
+fn push_run(out: &mut String, run: Option<(char, usize)>) {
+    match run {
+        Some((c, count)) if count > 3 => *out += &format!("!{count}{c}"),
+        Some((c, count)) => out.extend(std::iter::repeat_n(c, count)),
+        None => {}
+    }
+}
+
+fn color(color: Color) -> style::Color {
+    style::Color::Rgb {
+        r: color.r,
+        g: color.g,
+        b: color.b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chirp_8::machine::{HEIGHT, WIDTH};
+
+    use super::*;
+
+    fn buffer() -> Framebuffer {
+        let mut buffer = [[false; HEIGHT as usize]; WIDTH as usize];
+        buffer[0][0] = true;
+        buffer[1][3] = true;
+
+        buffer
+    }
+
+    #[test]
+    fn half_block_rows() {
+        let out = half_blocks(&buffer(), &Palette::default());
+
+        assert_eq!(out.matches('▀').count(), 64 * 16);
+        assert_eq!(out.matches("\r\n").count(), 15);
+    }
+
+    #[test]
+    fn braille_patterns() {
+        let out = braille(&buffer(), &Palette::default());
+        let cells = out
+            .chars()
+            .filter(|c| ('\u{2800}'..='\u{28FF}').contains(c));
+
+        // the top left pixel and the bottom right one of the first cell
+        assert_eq!(cells.clone().next(), Some('\u{2881}'));
+        assert_eq!(cells.count(), 32 * 8);
+    }
+
+    #[test]
+    fn sixel_image() {
+        let out = sixel(&buffer(), &Palette::default(), 1);
+
+        assert!(out.starts_with("\x1bPq\"1;1;64;32#0;2;0;0;0#1;2;100;100;100"));
+        assert!(out.ends_with("-\x1b\\"));
+        // 32 rows are six bands, the last one only partially used
+        assert_eq!(out.matches('-').count(), 6);
+    }
+}
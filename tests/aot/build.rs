@@ -0,0 +1,23 @@
+//! Translates the test ROMs with the current translator, so the modules the tests compile
+//! are never out of date.
+
+use std::{fmt::Write as _, path::PathBuf};
+
+#[path = "../roms/mod.rs"]
+mod roms;
+
+fn main() {
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+    let mut modules = String::new();
+
+    for (name, words) in roms::ROMS {
+        let module = name.trim_end_matches(".ch8");
+        let path = out_dir.join(format!("{module}.rs"));
+
+        std::fs::write(&path, chirp_8::aot::translate(&roms::bytes(words), name)).unwrap();
+        writeln!(modules, "#[path = {:?}]\npub mod {module};", path.display()).unwrap();
+    }
+
+    std::fs::write(out_dir.join("modules.rs"), modules).unwrap();
+    println!("cargo:rerun-if-changed=../roms/mod.rs");
+}
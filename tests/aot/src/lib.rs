@@ -0,0 +1,74 @@
+//! Modules generated by `chirp-8 aot` for the ROMs in `tests/roms`, translated by the build
+//! script and checked against the interpreter.
+
+include!(concat!(env!("OUT_DIR"), "/modules.rs"));
+
+#[cfg(test)]
+mod tests {
+    use chirp_8::machine::{Machine, Quirks};
+    use color_eyre::Result;
+
+    use super::*;
+
+    const INSTRUCTIONS: usize = 5_000;
+
+    fn compare(rom: &[u8], run_block: fn(&mut Machine) -> Result<usize>, quirks: Quirks) {
+        let mut reference = Machine::new(rom, quirks);
+        let mut translated = Machine::new(rom, quirks);
+        reference.seed_rng(0x8);
+        translated.seed_rng(0x8);
+        let mut executed = 0;
+
+        while executed < INSTRUCTIONS {
+            let ran = run_block(&mut translated).unwrap();
+            for _ in 0..ran {
+                reference.step().unwrap();
+            }
+            executed += ran;
+
+            assert_eq!(reference.pc, translated.pc, "after {executed} instructions");
+            assert_eq!(
+                reference.cycles, translated.cycles,
+                "after {executed} instructions"
+            );
+            assert_eq!(
+                reference.regs, translated.regs,
+                "after {executed} instructions"
+            );
+            assert_eq!(
+                reference.index, translated.index,
+                "after {executed} instructions"
+            );
+            assert_eq!(
+                reference.stack, translated.stack,
+                "after {executed} instructions"
+            );
+            assert_eq!(
+                (reference.delay_timer, reference.sound_timer),
+                (translated.delay_timer, translated.sound_timer),
+                "after {executed} instructions"
+            );
+            assert_eq!(
+                reference.framebuffer, translated.framebuffer,
+                "after {executed} instructions"
+            );
+            assert!(reference.memory.as_bytes() == translated.memory.as_bytes());
+        }
+    }
+
+    #[test]
+    fn translated_matches_interpreter() {
+        let all = Quirks {
+            old_shift: true,
+            new_jump: true,
+            old_store_load: true,
+            index_overflow: true,
+        };
+
+        for quirks in [Quirks::default(), all] {
+            compare(alu::ROM, alu::run_block, quirks);
+            compare(calls::ROM, calls::run_block, quirks);
+            compare(self_modify::ROM, self_modify::run_block, quirks);
+        }
+    }
+}
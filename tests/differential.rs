@@ -0,0 +1,142 @@
+//! Runs every ROM in `tests/roms` on both the reference interpreter and the threaded engine,
+//! comparing the complete machine state after every block. Point `CHIP8_TEST_ROMS` at a
+//! directory of ROMs, like a checkout of Timendus' chip8-test-suite, to compare those too.
+
+use std::path::PathBuf;
+
+use chirp_8::{
+    instruction::MAX_BLOCK_LEN,
+    machine::{Machine, Quirks},
+    memory::RegIdx,
+    threaded::Threaded,
+};
+
+mod roms;
+
+const INSTRUCTIONS: usize = 20_000;
+
+fn assert_same(rom: &str, executed: usize, reference: &Machine, threaded: &Machine) {
+    let context = || format!("{rom} after {executed} instructions");
+
+    assert_eq!(reference.pc, threaded.pc, "pc differs: {}", context());
+    assert_eq!(
+        reference.regs,
+        threaded.regs,
+        "registers differ: {}",
+        context()
+    );
+    assert_eq!(
+        reference.index,
+        threaded.index,
+        "index differs: {}",
+        context()
+    );
+    assert_eq!(
+        reference.stack,
+        threaded.stack,
+        "stack differs: {}",
+        context()
+    );
+    assert_eq!(
+        reference.cycles,
+        threaded.cycles,
+        "cycles differ: {}",
+        context()
+    );
+    assert_eq!(
+        (reference.delay_timer, reference.sound_timer),
+        (threaded.delay_timer, threaded.sound_timer),
+        "timers differ: {}",
+        context()
+    );
+    assert_eq!(
+        reference.framebuffer,
+        threaded.framebuffer,
+        "framebuffer differs: {}",
+        context()
+    );
+    assert!(
+        reference.memory.as_bytes() == threaded.memory.as_bytes(),
+        "memory differs: {}",
+        context()
+    );
+}
+
+fn compare(rom: &str, data: &[u8], quirks: Quirks) {
+    let mut reference = Machine::new(data, quirks);
+    let mut threaded = Machine::new(data, quirks);
+    reference.seed_rng(0x8);
+    threaded.seed_rng(0x8);
+
+    let mut engine = Threaded::new();
+    let mut executed = 0;
+
+    while executed < INSTRUCTIONS {
+        // test suites end in an endless loop or an error, both engines have to agree on it
+        let ran = match engine.run_block(&mut threaded) {
+            Ok(ran) => ran,
+            Err(err) => {
+                let reference_err = (0..MAX_BLOCK_LEN)
+                    .find_map(|_| reference.step().err())
+                    .unwrap_or_else(|| panic!("only the threaded engine failed on {rom}: {err}"));
+                assert_eq!(reference_err.to_string(), err.to_string(), "{rom}");
+                assert_same(rom, executed, &reference, &threaded);
+                return;
+            }
+        };
+        for _ in 0..ran {
+            reference.step().unwrap();
+        }
+        executed += ran;
+
+        assert_same(rom, executed, &reference, &threaded);
+    }
+}
+
+#[test]
+fn threaded_matches_interpreter() {
+    let all = Quirks {
+        old_shift: true,
+        new_jump: true,
+        old_store_load: true,
+        index_overflow: true,
+    };
+
+    let mut roms = roms::ROMS
+        .map(|(name, words)| (name.to_owned(), roms::bytes(words)))
+        .to_vec();
+    if let Some(dir) = std::env::var_os("CHIP8_TEST_ROMS") {
+        let mut paths = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "ch8"))
+            .collect::<Vec<PathBuf>>();
+        paths.sort();
+
+        assert!(!paths.is_empty(), "no ROMs in {}", dir.to_string_lossy());
+        for path in paths {
+            let data = std::fs::read(&path).unwrap();
+            roms.push((path.display().to_string(), data));
+        }
+    }
+
+    for (name, data) in &roms {
+        compare(name, data, Quirks::default());
+        compare(name, data, all);
+    }
+}
+
+#[test]
+fn self_modifying_code() {
+    let data = roms::bytes(roms::SELF_MODIFY);
+    let mut machine = Machine::new(&data, Quirks::default());
+
+    Threaded::new().run(&mut machine, 1_000).unwrap();
+
+    // VB accumulated 1 + 1 + 2 + ... + 31, VD holds the last patched immediate
+    assert_eq!(
+        machine.regs[RegIdx::new(0xB)],
+        (1 + (1..32).sum::<u32>()) as u8
+    );
+    assert_eq!(machine.regs[RegIdx::new(0xD)], 0x20);
+}
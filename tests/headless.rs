@@ -0,0 +1,123 @@
+//! Runs the emulator binary in headless mode and checks what it writes.
+
+use std::{path::PathBuf, process::Command};
+
+/// A unique path in the temporary directory
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("chirp-8-headless-{}-{name}", std::process::id()))
+}
+
+#[test]
+fn wav_follows_sound_timer() {
+    let rom = temp_path("beep.ch8");
+    let wav = temp_path("beep.wav");
+    // V0 = 6, sound timer = V0, jump to itself
+    std::fs::write(&rom, [0x60, 0x06, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_chirp-8"))
+        .args([
+            "--headless",
+            "--frames",
+            "30",
+            "--ips",
+            "600",
+            "--release",
+            "0",
+        ])
+        .args(["--waveform", "square", "--wav"])
+        .args([&wav, &rom])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let samples = hound::WavReader::open(&wav)
+        .unwrap()
+        .into_samples::<i16>()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    std::fs::remove_file(rom).unwrap();
+    std::fs::remove_file(wav).unwrap();
+
+    // half a second at 48 kHz, 80 samples per instruction
+    assert_eq!(samples.len(), 24000);
+    // the beep starts with the second instruction and lasts six timer ticks of ten
+    // instructions each
+    let start = samples.iter().position(|&s| s != 0).unwrap();
+    let end = samples.iter().rposition(|&s| s != 0).unwrap();
+    assert_eq!(start, 2 * 80);
+    assert_eq!(end + 1, 60 * 80);
+}
+
+#[test]
+fn threaded_engine_draws_the_same() {
+    let rom = temp_path("count.ch8");
+    // add 3 to V0 100 times, draw the font digit of its low nibble and stop
+    std::fs::write(
+        &rom,
+        [
+            0x60, 0x00, 0x70, 0x03, 0x71, 0x01, 0x31, 0x64, 0x12, 0x02, 0xF0, 0x29, 0xD2, 0x25,
+            0x12, 0x0E,
+        ],
+    )
+    .unwrap();
+
+    let screenshot = |threaded: bool| {
+        let path = temp_path(&format!("count-{threaded}.pbm"));
+        let status = Command::new(env!("CARGO_BIN_EXE_chirp-8"))
+            .args(["--headless", "--frames", "50", "--screenshot"])
+            .arg(&path)
+            .args(threaded.then_some("--threaded"))
+            .arg(&rom)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let image = std::fs::read(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        image
+    };
+
+    let (interpreted, threaded) = (screenshot(false), screenshot(true));
+    std::fs::remove_file(rom).unwrap();
+    assert_eq!(interpreted, threaded);
+}
+
+#[test]
+fn screenshots_during_the_run() {
+    let rom = temp_path("wait.ch8");
+    let shot = temp_path("wait.pbm");
+    // wait 30 frames on the delay timer, then draw the 0 of the font at the top left
+    std::fs::write(
+        &rom,
+        [
+            0x60, 0x1E, 0xF0, 0x15, 0xF0, 0x07, 0x30, 0x00, 0x12, 0x04, 0xF0, 0x29, 0xD1, 0x15,
+            0x12, 0x0E,
+        ],
+    )
+    .unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_chirp-8"))
+        .args(["--headless", "--frames", "60", "--screenshot"])
+        .arg(&shot)
+        .args(["--screenshot-at", "10", "--screenshot-at", "40"])
+        .arg(&rom)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let read = |frame: u64| {
+        let path = temp_path(&format!("wait-{frame}.pbm"));
+        let image = std::fs::read(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        image
+    };
+    let (waiting, drawn) = (read(10), read(40));
+    std::fs::remove_file(rom).unwrap();
+
+    // the header is followed by the pixels, the first row has the top of the 0
+    let header = b"P4\n64 32\n".len();
+    assert!(waiting[header..].iter().all(|&byte| byte == 0));
+    assert_eq!(drawn[header], 0xF0);
+    // only the requested frames were saved
+    assert!(!shot.exists());
+}
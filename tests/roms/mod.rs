@@ -0,0 +1,96 @@
+//! ROMs for the integration tests, written out word by word with the Octo source of each
+//! instruction. [`ROMS`] names them like files so failures point at the right one.
+
+/// Every ALU instruction, the BCD/store/load trio, fonts, drawing and a random number,
+/// looped 64 times so results feed back into the next round
+pub const ALU: &[u16] = &[
+    0x6A00, // 200: va := 0
+    //         loop:
+    0x6037, // 202: v0 := 0x37
+    0x61C9, // 204: v1 := 0xc9
+    0x8014, // 206: v0 += v1
+    0x8200, // 208: v2 := v0
+    0x8215, // 20a: v2 -= v1
+    0x8317, // 20c: v3 =- v1
+    0x8416, // 20e: v4 >>= v1
+    0x850E, // 210: v5 <<= v0
+    0x8601, // 212: v6 |= v0
+    0x8612, // 214: v6 &= v1
+    0x8623, // 216: v6 ^= v2
+    0xC7FF, // 218: v7 := random 0xff
+    0xA300, // 21a: i := 0x300
+    0xF033, // 21c: bcd v0
+    0xF655, // 21e: save v6
+    0xF265, // 220: load v2
+    0xF029, // 222: i := hex v0
+    0xD015, // 224: sprite v0 v1 5
+    0xF11E, // 226: i += v1
+    0x7A01, // 228: va += 1
+    0x3A40, // 22a: if va != 0x40 then
+    0x1202, // 22c: jump loop
+    0x122E, // 22e: jump 0x22e
+];
+
+/// Calls, register skips, a computed jump over two zero words, the timers, key skips and
+/// immediate skips
+pub const CALLS: &[u16] = &[
+    0x6004, // 200: v0 := 4
+    0x6100, // 202: v1 := 0
+    //         loop:
+    0x2230, // 204: :call increment
+    0x9010, // 206: if v0 == v1 then
+    0x120C, // 208: jump done
+    0x1204, // 20a: jump loop
+    //         done:
+    0x6204, // 20c: v2 := 4
+    0xB210, // 20e: jump0 0x210, lands on 0x214 with v0 = 4
+    0x0000, // 210: never executed
+    0x0000, // 212: never executed
+    0xF215, // 214: delay := v2
+    0xF218, // 216: buzzer := v2
+    0xF307, // 218: v3 := delay
+    0xE39E, // 21a: if v3 -key then
+    0xE3A1, // 21c: if v3 key then
+    0x00E0, // 21e: clear
+    0x4404, // 220: if v4 == 4 then
+    0x00E0, // 222: clear
+    0x3400, // 224: if v4 != 0 then
+    0x00E0, // 226: clear
+    0x1228, // 228: jump 0x228
+    0x0000, // 22a: padding
+    0x0000, // 22c: padding
+    0x0000, // 22e: padding
+    //         increment:
+    0x7101, // 230: v1 += 1
+    0x00EE, // 232: return
+];
+
+/// Patches the immediates of two of its own instructions every round: `vb += 1` at 204
+/// adds the round number from then on and `vd := 0` at 212 loads it
+pub const SELF_MODIFY: &[u16] = &[
+    0x6C00, // 200: vc := 0
+    0x6B00, // 202: vb := 0
+    //         loop:
+    0x7B01, // 204: vb += 1, the immediate is patched
+    0x7C01, // 206: vc += 1
+    0x80C0, // 208: v0 := vc
+    0xA205, // 20a: i := 0x205, the immediate of 204
+    0xF055, // 20c: save v0
+    0xA213, // 20e: i := 0x213, the immediate of 212
+    0xF055, // 210: save v0
+    0x6D00, // 212: vd := 0, the immediate is patched
+    0x3C20, // 214: if vc != 0x20 then
+    0x1204, // 216: jump loop
+    0x1218, // 218: jump 0x218
+];
+
+pub const ROMS: [(&str, &[u16]); 3] = [
+    ("alu.ch8", ALU),
+    ("calls.ch8", CALLS),
+    ("self_modify.ch8", SELF_MODIFY),
+];
+
+/// The ROM as it's loaded, big endian
+pub fn bytes(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_be_bytes()).collect()
+}